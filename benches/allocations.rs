@@ -0,0 +1,72 @@
+//! Counts heap allocations made by `transpile`, `transpile_into` and a
+//! reused `Transpiler` over many small templates. Run with `cargo bench --bench allocations`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use sline_transpiler::{Options, Transpiler, transpile, transpile_into};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const SNIPPETS: &[&str] = &[
+    "<h1>{{title}}</h1>",
+    "{{#each products as |product|}}<li>{{product.title}}</li>{{/each}}",
+    "{{#if user.admin}}Admin{{else}}Guest{{/if}}",
+    "{{> header title=page.title}}",
+];
+const ROUNDS: usize = 10_000;
+
+fn measure(name: &str, mut run: impl FnMut(&str)) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for snippet in SNIPPETS {
+            run(snippet);
+        }
+    }
+    let elapsed = start.elapsed();
+    let calls = ROUNDS * SNIPPETS.len();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "{name:>14}: {:.1} allocations/call, {:.2} µs/call",
+        allocations as f64 / calls as f64,
+        elapsed.as_secs_f64() * 1e6 / calls as f64
+    );
+}
+
+fn main() {
+    let options = Options::default();
+    measure("transpile", |snippet| {
+        std::hint::black_box(transpile(snippet, &options));
+    });
+    let mut output = String::new();
+    measure("transpile_into", |snippet| {
+        std::hint::black_box(transpile_into(snippet, &options, &mut output));
+    });
+    let mut transpiler = Transpiler::new();
+    measure("Transpiler", |snippet| {
+        std::hint::black_box(transpiler.transpile(snippet, &options));
+    });
+}
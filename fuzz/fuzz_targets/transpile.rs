@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sline_transpiler::{Options, check_syntax, transpile_detailed};
+
+fuzz_target!(|input: &str| {
+    check_syntax(input);
+
+    let options = Options {
+        allow_parent: true,
+        indent: Some(2),
+        ..Options::default()
+    };
+    for options in [Options::default(), options] {
+        let (segments, _) = transpile_detailed(input, &options);
+        // Segments must tile both the input and the output without gaps.
+        let mut source = 0;
+        let mut output = 0;
+        for segment in &segments {
+            assert!(segment.source.start >= source && segment.source.end <= input.len());
+            assert_eq!(segment.output, output..output + segment.text.len());
+            source = segment.source.end;
+            output = segment.output.end;
+        }
+    }
+});
@@ -0,0 +1,159 @@
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Equal,
+    Delete,
+    Insert,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edit {
+    kind: Kind,
+    old: usize,
+    new: usize,
+}
+
+/// Renders a line-based unified diff between `old` and `new`, or `None` when
+/// they are identical.
+pub fn unified(name: &str, old: &str, new: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edit_script(&old_lines, &new_lines);
+
+    let mut output = format!("--- {name}\n+++ {name}\n");
+    for (low, high) in hunks(&edits) {
+        let hunk = &edits[low..high];
+        let old_count = hunk.iter().filter(|edit| edit.kind != Kind::Insert).count();
+        let new_count = hunk.iter().filter(|edit| edit.kind != Kind::Delete).count();
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(hunk[0].old, old_count),
+            range(hunk[0].new, new_count)
+        ));
+        for edit in hunk {
+            let (prefix, line) = match edit.kind {
+                Kind::Equal => (' ', old_lines[edit.old]),
+                Kind::Delete => ('-', old_lines[edit.old]),
+                Kind::Insert => ('+', new_lines[edit.new]),
+            };
+            output.push(prefix);
+            output.push_str(line);
+            if !line.ends_with('\n') {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    Some(output)
+}
+
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{count}", start + 1),
+    }
+}
+
+/// Groups the changed edits into hunks of `CONTEXT` surrounding lines,
+/// merging changes that are close enough to share context.
+fn hunks(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for (position, edit) in edits.iter().enumerate() {
+        if edit.kind == Kind::Equal {
+            continue;
+        }
+        match groups.last_mut() {
+            Some((_, end)) if position - *end <= 2 * CONTEXT => *end = position,
+            _ => groups.push((position, position)),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(start, end)| {
+            (
+                start.saturating_sub(CONTEXT),
+                (end + CONTEXT + 1).min(edits.len()),
+            )
+        })
+        .collect()
+}
+
+/// Computes the shortest edit script with Myers' algorithm.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let offset = n + m + 1;
+    let at = |k: isize| (k + offset) as usize;
+    let mut frontier = vec![0isize; (2 * offset + 1) as usize];
+    let mut trace = Vec::new();
+
+    'search: for depth in 0..=(n + m) {
+        trace.push(frontier.clone());
+        for k in (-depth..=depth).step_by(2) {
+            let mut x = if k == -depth || (k != depth && frontier[at(k - 1)] < frontier[at(k + 1)])
+            {
+                frontier[at(k + 1)]
+            } else {
+                frontier[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            frontier[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (depth, frontier) in trace.iter().enumerate().rev() {
+        let depth = depth as isize;
+        let k = x - y;
+        let previous_k = if k == -depth || (k != depth && frontier[at(k - 1)] < frontier[at(k + 1)])
+        {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = frontier[at(previous_k)];
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit {
+                kind: Kind::Equal,
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if depth > 0 {
+            if x == previous_x {
+                y -= 1;
+                edits.push(Edit {
+                    kind: Kind::Insert,
+                    old: x as usize,
+                    new: y as usize,
+                });
+            } else {
+                x -= 1;
+                edits.push(Edit {
+                    kind: Kind::Delete,
+                    old: x as usize,
+                    new: y as usize,
+                });
+            }
+        }
+    }
+    edits.reverse();
+    edits
+}
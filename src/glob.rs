@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Whether `path` contains glob metacharacters.
+pub fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Expands a glob pattern into the sorted list of matching paths.
+///
+/// Supports `*`, `?`, `[abc]`/`[a-z]`/`[!abc]` classes within a path
+/// component, and `**` as a whole component matching any number of
+/// directories. As in a shell, wildcards do not match names starting with
+/// a dot unless the pattern component does.
+pub fn expand(pattern: &str) -> Vec<PathBuf> {
+    let separators: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+    let mut parts: Vec<&str> = pattern.split(separators).collect();
+    let base = if parts.first() == Some(&"") {
+        parts.remove(0);
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    };
+    parts.retain(|part| !part.is_empty());
+
+    let mut matches = Vec::new();
+    walk(base, &parts, &mut matches);
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+fn walk(base: PathBuf, parts: &[&str], matches: &mut Vec<PathBuf>) {
+    let Some((&part, rest)) = parts.split_first() else {
+        if !base.as_os_str().is_empty() && base.exists() {
+            matches.push(base);
+        }
+        return;
+    };
+
+    if part == "**" {
+        walk(base.clone(), rest, matches);
+        for entry in entries(&base) {
+            if entry.is_dir() && !is_hidden(&entry) {
+                walk(entry, parts, matches);
+            }
+        }
+        return;
+    }
+
+    if !is_pattern(part) {
+        walk(base.join(part), rest, matches);
+        return;
+    }
+
+    let pattern: Vec<char> = part.chars().collect();
+    for entry in entries(&base) {
+        let Some(name) = entry.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if name.starts_with('.') && !part.starts_with('.') {
+            continue;
+        }
+        let name: Vec<char> = name.chars().collect();
+        if matches_component(&pattern, &name) {
+            walk(entry, rest, matches);
+        }
+    }
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let listed = if dir.as_os_str().is_empty() {
+        fs::read_dir(".")
+    } else {
+        fs::read_dir(dir)
+    };
+    let Ok(listed) = listed else {
+        return Vec::new();
+    };
+    let mut entries: Vec<PathBuf> = listed
+        .filter_map(|entry| entry.ok())
+        .map(|entry| dir.join(entry.file_name()))
+        .collect();
+    entries.sort();
+    entries
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_component(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_component(&pattern[1..], &name[1..]),
+        Some('[') => match (parse_class(pattern), name.first()) {
+            (Some((length, class)), Some(&character)) => {
+                class(character) && matches_component(&pattern[length..], &name[1..])
+            }
+            (Some(_), None) => false,
+            (None, _) => name.first() == Some(&'[') && matches_component(&pattern[1..], &name[1..]),
+        },
+        Some(literal) => {
+            name.first() == Some(literal) && matches_component(&pattern[1..], &name[1..])
+        }
+    }
+}
+
+/// Parses a `[...]` class at the start of `pattern`, returning its length
+/// and a predicate, or `None` when the bracket is never closed.
+fn parse_class(pattern: &[char]) -> Option<(usize, impl Fn(char) -> bool + '_)> {
+    let mut index = 1;
+    let negated = matches!(pattern.get(index), Some('!' | '^'));
+    if negated {
+        index += 1;
+    }
+    let start = index;
+    // A `]` right after the opening bracket is a literal member.
+    if pattern.get(index) == Some(&']') {
+        index += 1;
+    }
+    while pattern
+        .get(index)
+        .is_some_and(|&character| character != ']')
+    {
+        index += 1;
+    }
+    pattern.get(index)?;
+
+    let members = &pattern[start..index];
+    let class = move |character: char| {
+        let mut found = false;
+        let mut position = 0;
+        while position < members.len() {
+            if members.get(position + 1) == Some(&'-') && position + 2 < members.len() {
+                found |= (members[position]..=members[position + 2]).contains(&character);
+                position += 3;
+            } else {
+                found |= members[position] == character;
+                position += 1;
+            }
+        }
+        found != negated
+    };
+    Some((index + 1, class))
+}
@@ -0,0 +1,5627 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+use std::ops::Range;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    /// A diagnostic reported at `code`'s [`Code::level`].
+    pub fn new(code: Code, message: String, span: Span) -> Self {
+        Diagnostic {
+            level: code.level(),
+            code,
+            message,
+            span,
+        }
+    }
+}
+
+/// Stable identifier for each kind of diagnostic, printed as `SL001` etc.
+/// Codes are never reused or renumbered once released.
+///
+/// ```
+/// use sline_transpiler::{Code, Options, transpile};
+///
+/// let (output, diagnostics) = transpile("{{#unless a}}no{{else}}yes{{/unless}}", &Options::default());
+/// assert_eq!(output, "{{ #if !(a) }}no{{ else }}yes{{ /if }}");
+/// assert_eq!(diagnostics[0].code, Code::UnlessElse);
+/// assert_eq!(diagnostics[0].code.as_str(), "SL016");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// SL001: `../` parent scope access that cannot be resolved.
+    ParentScope,
+    /// SL002: `../` resolved to an enclosing scope with `allow_parent`.
+    ParentScopeResolved,
+    /// SL003: `{{this}}` or a loop data variable outside of any `#each`.
+    NoEachContext,
+    /// SL004: `@key` in a loop without a key binding.
+    MissingKeyBinding,
+    /// SL005: `@root.` path shadowed by a block param.
+    RootShadowed,
+    /// SL006: loop alias shadowing an enclosing loop's alias.
+    AliasShadowed,
+    /// SL007: `../` climbing to the top-level `this` from inside a block.
+    ParentScopeThis,
+    /// SL008: `@index` and `@key` in one loop sharing its second block param.
+    IndexAndKey,
+    /// SL009: `@../index` or another parent loop data variable that cannot
+    /// be resolved.
+    ParentDataVariable,
+    /// SL010: `#with` block that is left unconverted.
+    UnconvertedWith,
+    /// SL011: chained `else if` inside `#each`.
+    ChainedElseInEach,
+    /// SL012: inline partial definition.
+    InlinePartial,
+    /// SL013: mustache section or inverted section converted to `#if`.
+    SectionAsIf,
+    /// SL014: `#each` hash arguments removed from the loop header.
+    DroppedHashArguments,
+    /// SL015: `{{*decorator}}` that is left unconverted.
+    Decorator,
+    /// SL016: `{{else}}` of an `#unless`, which runs when its condition holds.
+    UnlessElse,
+    /// SL017: block keyword such as `#EACH` lowercased by
+    /// `case_insensitive_blocks`.
+    KeywordCase,
+    /// SL020: partial invocation without a name.
+    MissingPartialName,
+    /// SL021: dynamic partial that is left unconverted.
+    DynamicPartial,
+    /// SL022: helper that is neither built in nor mapped.
+    UnknownHelper,
+    /// SL023: `lookup` call that is left unconverted.
+    UnconvertedLookup,
+    /// SL024: debugging helper call removed from the output.
+    DroppedHelper,
+    /// SL025: partial with more than one context argument.
+    ExtraPartialContext,
+    /// SL030: empty `{{}}` tag.
+    EmptyTag,
+    /// SL031: block, raw block or comment that is never closed.
+    UnclosedBlock,
+    /// SL032: closing tag that does not match the innermost open block.
+    MismatchedClose,
+    /// SL033: closing tag without any open block.
+    UnexpectedClose,
+    /// SL034: `{{else}}` outside of a block that accepts it.
+    StrayElse,
+    /// SL035: `#if` or `#unless` without a condition.
+    MissingCondition,
+    /// SL037: `#each` without an iterable, or with block params that are
+    /// not `as |item|` or `as |item index|`.
+    MalformedEach,
+    /// SL036: `{{` without a matching `}}`.
+    UnterminatedTag,
+    /// SL040: non-blank input that produced blank output.
+    EmptyOutput,
+    /// SL041: `{{{triple}}}` or `{{&name}}` output that is not HTML-escaped.
+    UnescapedOutput,
+    /// SL097: an input or output file could not be read or written.
+    Io,
+    /// SL098: a second pass over the output changes it.
+    NotIdempotent,
+    /// SL099: the transpiler failed unexpectedly.
+    Internal,
+}
+
+impl Code {
+    pub const ALL: [Code; 36] = [
+        Code::ParentScope,
+        Code::ParentScopeResolved,
+        Code::NoEachContext,
+        Code::MissingKeyBinding,
+        Code::RootShadowed,
+        Code::AliasShadowed,
+        Code::ParentScopeThis,
+        Code::IndexAndKey,
+        Code::ParentDataVariable,
+        Code::UnconvertedWith,
+        Code::ChainedElseInEach,
+        Code::InlinePartial,
+        Code::SectionAsIf,
+        Code::DroppedHashArguments,
+        Code::Decorator,
+        Code::UnlessElse,
+        Code::KeywordCase,
+        Code::MissingPartialName,
+        Code::DynamicPartial,
+        Code::UnknownHelper,
+        Code::UnconvertedLookup,
+        Code::DroppedHelper,
+        Code::ExtraPartialContext,
+        Code::EmptyTag,
+        Code::UnclosedBlock,
+        Code::MismatchedClose,
+        Code::UnexpectedClose,
+        Code::StrayElse,
+        Code::MissingCondition,
+        Code::MalformedEach,
+        Code::UnterminatedTag,
+        Code::EmptyOutput,
+        Code::UnescapedOutput,
+        Code::Io,
+        Code::NotIdempotent,
+        Code::Internal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::ParentScope => "SL001",
+            Code::ParentScopeResolved => "SL002",
+            Code::NoEachContext => "SL003",
+            Code::MissingKeyBinding => "SL004",
+            Code::RootShadowed => "SL005",
+            Code::AliasShadowed => "SL006",
+            Code::ParentScopeThis => "SL007",
+            Code::IndexAndKey => "SL008",
+            Code::ParentDataVariable => "SL009",
+            Code::UnconvertedWith => "SL010",
+            Code::ChainedElseInEach => "SL011",
+            Code::InlinePartial => "SL012",
+            Code::SectionAsIf => "SL013",
+            Code::DroppedHashArguments => "SL014",
+            Code::Decorator => "SL015",
+            Code::UnlessElse => "SL016",
+            Code::KeywordCase => "SL017",
+            Code::MissingPartialName => "SL020",
+            Code::DynamicPartial => "SL021",
+            Code::UnknownHelper => "SL022",
+            Code::UnconvertedLookup => "SL023",
+            Code::DroppedHelper => "SL024",
+            Code::ExtraPartialContext => "SL025",
+            Code::EmptyTag => "SL030",
+            Code::UnclosedBlock => "SL031",
+            Code::MismatchedClose => "SL032",
+            Code::UnexpectedClose => "SL033",
+            Code::StrayElse => "SL034",
+            Code::MissingCondition => "SL035",
+            Code::MalformedEach => "SL037",
+            Code::UnterminatedTag => "SL036",
+            Code::EmptyOutput => "SL040",
+            Code::UnescapedOutput => "SL041",
+            Code::Io => "SL097",
+            Code::NotIdempotent => "SL098",
+            Code::Internal => "SL099",
+        }
+    }
+
+    /// The level diagnostics with this code are reported at.
+    pub fn level(self) -> Level {
+        match self {
+            Code::ParentScope
+            | Code::ParentDataVariable
+            | Code::MissingPartialName
+            | Code::UnclosedBlock
+            | Code::MismatchedClose
+            | Code::UnexpectedClose
+            | Code::StrayElse
+            | Code::MissingCondition
+            | Code::MalformedEach
+            | Code::UnterminatedTag
+            | Code::Io
+            | Code::NotIdempotent
+            | Code::Internal => Level::Error,
+            _ => Level::Warning,
+        }
+    }
+
+    /// A one-line description of what this code reports.
+    pub fn description(self) -> &'static str {
+        match self {
+            Code::ParentScope => "`../` parent scope access that cannot be resolved",
+            Code::ParentScopeResolved => "`../` resolved to an enclosing scope with `allow_parent`",
+            Code::NoEachContext => "`{{this}}` or a loop data variable outside of any `#each`",
+            Code::MissingKeyBinding => "`@key` in a loop without a key binding",
+            Code::RootShadowed => "`@root.` path shadowed by a block param",
+            Code::AliasShadowed => "Loop alias shadowing an enclosing loop's alias",
+            Code::ParentScopeThis => "`../` climbing to the top-level `this` from inside a block",
+            Code::IndexAndKey => "`@index` and `@key` in one loop sharing its second block param",
+            Code::ParentDataVariable => "parent loop data variable that cannot be resolved",
+            Code::UnconvertedWith => "`#with` block that is left unconverted",
+            Code::ChainedElseInEach => "Chained `else if` inside `#each`",
+            Code::InlinePartial => "Inline partial definition",
+            Code::SectionAsIf => "Mustache section or inverted section converted to `#if`",
+            Code::DroppedHashArguments => "`#each` hash arguments removed from the loop header",
+            Code::Decorator => "`{{*decorator}}` that is left unconverted",
+            Code::UnlessElse => "`{{else}}` of an `#unless`, which runs when its condition holds",
+            Code::KeywordCase => "block keyword that is not lowercase",
+            Code::MissingPartialName => "Partial invocation without a name",
+            Code::DynamicPartial => "Dynamic partial that is left unconverted",
+            Code::UnknownHelper => "Helper that is neither built in nor mapped",
+            Code::UnconvertedLookup => "`lookup` call that is left unconverted",
+            Code::DroppedHelper => "Debugging helper call removed from the output",
+            Code::ExtraPartialContext => "Partial with more than one context argument",
+            Code::EmptyTag => "Empty `{{}}` tag",
+            Code::UnclosedBlock => "Block, raw block or comment that is never closed",
+            Code::MismatchedClose => "Closing tag that does not match the innermost open block",
+            Code::UnexpectedClose => "Closing tag without any open block",
+            Code::StrayElse => "`{{else}}` outside of a block that accepts it",
+            Code::MissingCondition => "`#if` or `#unless` without a condition",
+            Code::MalformedEach => "`#each` without an iterable or with invalid block params",
+            Code::UnterminatedTag => "`{{` without a matching `}}`",
+            Code::EmptyOutput => "Non-blank input that produced blank output",
+            Code::UnescapedOutput => {
+                "`{{{triple}}}` or `{{&name}}` output that is not HTML-escaped"
+            }
+            Code::Io => "An input or output file could not be read or written",
+            Code::NotIdempotent => "A second pass over the output changes it",
+            Code::Internal => "The transpiler failed unexpectedly",
+        }
+    }
+
+    /// A longer explanation of why this code is reported and how to fix
+    /// it, for `--explain`.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Code::ParentScope => {
+                "Sline has no `../` paths for reaching an outer scope from inside a block. Refer to the outer loop's alias instead (`{{#each products as |product|}}` \
+                 then `{{product.name}}`), or pass --allow-parent to have `../` rewritten to the \
+                 enclosing alias. Climbing above the top-level context is an error either way."
+            }
+            Code::ParentScopeResolved => {
+                "With --allow-parent, a `../` path was rewritten to the alias of the loop or \
+                 `#with` it climbs to. The result is usually right, but check it where blocks \
+                 are nested, since Handlebars counts every block that changes the context."
+            }
+            Code::NoEachContext => {
+                "`{{this}}`, `@index`, `@first`, `@last` and `@key` only mean something inside an \
+                 `#each`. Outside of one they are left as written; refer to the value by its \
+                 name instead, or move the tag into the loop it belongs to."
+            }
+            Code::MissingKeyBinding => {
+                "Sline only has a key variable when the `#for` binds one. Name it with block \
+                 params (`{{#each object as |value key|}}`) or pass --each-as-object to bind \
+                 `key` in every loop, and `@key` converts to it."
+            }
+            Code::RootShadowed => {
+                "`@root.` is dropped so the path resolves against the global context, but a \
+                 block param with the same name as the path's first segment now takes its \
+                 place. Rename the block param so the global value stays reachable."
+            }
+            Code::AliasShadowed => {
+                "A loop binds a name that an enclosing loop already binds, so references to \
+                 the outer one inside the inner loop now see the inner one. Rename one of the \
+                 block params."
+            }
+            Code::ParentScopeThis => {
+                "`../` climbed all the way to the top-level context, which Sline has no name \
+                 for inside a loop, so the path became `this`. Refer to the top-level value by \
+                 its name instead."
+            }
+            Code::IndexAndKey => {
+                "A loop uses both `@index` and `@key`. Handlebars binds the key of an object to \
+                 the second block param, and so does Sline, so once `@key` appears that param \
+                 means the key and later `@index` references become `forloop.index0`. An \
+                 `@index` before the first `@key` was already converted to the param; replace \
+                 it with `forloop.index0`."
+            }
+            Code::ParentDataVariable => {
+                "`@../index` refers to the index of an enclosing loop, one loop up per `../`. \
+                 Sline's `forloop` only describes the innermost loop, so the reference becomes \
+                 the enclosing loop's index block param, which this one does not have, or there \
+                 are not that many enclosing loops. Name the index with `as |item index|` or \
+                 bind one in every loop with --always-index."
+            }
+            Code::UnconvertedWith => {
+                "`#with` over a subexpression, or without an argument, cannot be turned into a \
+                 guarded `#if`. Assign the subexpression's result to a variable or use the full \
+                 paths inside the block, then remove the `#with`."
+            }
+            Code::ChainedElseInEach => {
+                "The `else` of a Sline `#for` runs when the list is empty and cannot be chained \
+                 with a condition. Wrap the loop in an `#if` for the extra branch."
+            }
+            Code::InlinePartial => {
+                "Sline has no inline partials. The block is kept with its body converted; move \
+                 the body into a partial file of its own and include it by name."
+            }
+            Code::SectionAsIf => {
+                "A mustache section such as `{{#items}}` or `{{^items}}` was converted to an \
+                 `#if`. Handlebars also loops over a section when its value is a list, which an \
+                 `#if` does not; use `#each` for lists, or test `items.size` for emptiness."
+            }
+            Code::DroppedHashArguments => {
+                "Hash arguments such as `limit=10` on an `#each` only mean something to a custom \
+                 iteration helper, and Sline's `#for` takes none. Apply them to the list \
+                 before the loop, for example with a filter."
+            }
+            Code::Decorator => {
+                "Decorators run when Handlebars compiles a template, and Sline has nothing like \
+                 them. The tag is left as written; reimplement what the decorator does and \
+                 remove it."
+            }
+            Code::UnlessElse => {
+                "`#unless x` became `#if !(x)`, so the `{{else}}` that follows is now the else of \
+                 a negated `#if` and runs when `x` is true. The conversion is right; when the \
+                 negation makes the block hard to read, swap the branches and use `#if x`."
+            }
+            Code::KeywordCase => {
+                "Handlebars keywords are case-sensitive, so a tag such as `{{#EACH items}}` or \
+                 `{{/If}}` only worked with a runtime that accepted that casing. The keyword \
+                 was lowercased and converted as usual; fix its casing in the template."
+            }
+            Code::MissingPartialName => {
+                "A `{{>}}` tag needs the name of the partial to include. Add the name or remove \
+                 the tag."
+            }
+            Code::DynamicPartial => {
+                "The partial to include is computed at render time, so the include cannot be \
+                 written with a fixed name. Pick the partial with an `#if` chain and include \
+                 each one by name."
+            }
+            Code::UnknownHelper => {
+                "The helper is not built in and has no mapping, so the call is copied as it \
+                 is and will fail if Sline has no helper of that name. Map it to the Sline \
+                 equivalent with --helpers, or rewrite the call. --fail-unknown-helpers makes \
+                 this an error."
+            }
+            Code::UnconvertedLookup => {
+                "Only `lookup` with two plain arguments becomes an index expression such as \
+                 `items[index]`. Rewrite the other forms as a path or with a helper."
+            }
+            Code::DroppedHelper => {
+                "Debugging helpers such as `log` have no output, so their calls were removed. \
+                 Map the helper with --helpers to keep the calls."
+            }
+            Code::ExtraPartialContext => {
+                "A partial takes at most one context argument before its hash arguments; the \
+                 extra ones were dropped. Pass the other values as `key=value` arguments."
+            }
+            Code::EmptyTag => {
+                "A `{{}}` tag has nothing to output, often the remains of an edit or a broken \
+                 concatenation. It was removed from the output; remove it from the template \
+                 or fill it in."
+            }
+            Code::UnclosedBlock => {
+                "A block, raw block or comment is still open at the end of the template. Add \
+                 its closing tag where the block should end."
+            }
+            Code::MismatchedClose => {
+                "The closing tag names a different block than the innermost open one. The \
+                 output closes the block that is actually open; fix the closing tag, or the \
+                 nesting, in the source."
+            }
+            Code::UnexpectedClose => {
+                "A closing tag appears where no block is open. Remove it, or add the opening \
+                 tag it belongs to."
+            }
+            Code::StrayElse => {
+                "`{{else}}` is only valid inside `#if`, `#unless`, `#each` and sections. Move \
+                 it into the block it belongs to or remove it."
+            }
+            Code::MissingCondition => {
+                "An `#if` or `#unless` needs a condition to test. Add the condition, or remove \
+                 the block."
+            }
+            Code::MalformedEach => {
+                "An `#each` needs the list to loop over, and its block params name at most the \
+                 item and its index, as in `{{#each items as |item index|}}`, closed by a second \
+                 `|`. A header without an iterable or with unclosed params is left as written; \
+                 params past the second are dropped."
+            }
+            Code::UnterminatedTag => {
+                "A `{{` is not closed before the next `{{` or the end of the template, usually \
+                 because of a typo such as `{{ name }`. The braces are copied as-is and the tags \
+                 after them are still converted. Add the missing `}}`."
+            }
+            Code::EmptyOutput => {
+                "The template has content but its output is blank, most likely because every \
+                 line was a comment dropped by --strip-comments or --comment-style strip."
+            }
+            Code::UnescapedOutput => {
+                "Only reported with --no-unescaped or --warn-unescaped. The tag writes its value \
+                 without HTML escaping, so a value carrying markup from users can inject HTML \
+                 or scripts. Check where the value comes from, and switch to `{{ }}` when it \
+                 does not need to contain markup."
+            }
+            Code::Io => {
+                "An input or output file could not be read or written. Check that the path \
+                 exists, is a file holding UTF-8 text, and is readable, or writable for output."
+            }
+            Code::NotIdempotent => {
+                "With --check-idempotent, converting the output a second time changed it, so \
+                 some rule misreads its own output. Escaped mustaches such as `\\{{x}}` are \
+                 always reported, since the first pass unescapes them. Otherwise, please report \
+                 the template as a bug."
+            }
+            Code::Internal => {
+                "The transpiler failed on this file. The other files were still converted. \
+                 Please report the template as a bug."
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Code {
+    type Err = String;
+
+    /// Parses a code such as `SL010`, ignoring ASCII case.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Code::ALL
+            .into_iter()
+            .find(|code| code.as_str().eq_ignore_ascii_case(value))
+            .ok_or_else(|| format!("Unknown diagnostic code: {value}"))
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Location of a diagnostic in the source. `start` and `end` are byte
+/// offsets; `line` and `column` are 1-based, with columns counted in chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Settings for a conversion. The defaults match the command line without
+/// any flags.
+///
+/// ```
+/// use sline_transpiler::{Options, transpile};
+///
+/// let options = Options {
+///     passthrough_blocks: vec!["markdown".to_string()],
+///     ..Options::default()
+/// };
+/// let (output, diagnostics) = transpile("{{#markdown}}# {{this.title}}{{/markdown}}", &options);
+/// assert_eq!(output, "{{ #markdown }}# {{ title }}{{ /markdown }}");
+/// assert!(diagnostics.is_empty());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub allow_parent: bool,
+    pub preserve_spacing: bool,
+    /// Handlebars helper names mapped to their Sline replacements. An empty
+    /// replacement drops calls to the helper from the output.
+    pub helpers: HashMap<String, String>,
+    /// Treat every `#each` as object iteration and bind a `key` variable,
+    /// so `@key` can be rewritten.
+    pub each_as_object: bool,
+    /// Loop alias used when an `#each` has no block params; `item` if unset.
+    pub default_alias: Option<String>,
+    /// Bind an index with this name in every `#for` whose `#each` names
+    /// none, so `@index` converts to it. Loops that bind a key through
+    /// `each_as_object` are left alone.
+    pub always_index: Option<String>,
+    /// Helper whose `(name start end)` calls as an `#each` iterable become
+    /// a Sline `start..end` range; `range` if unset. Ignored when the helper
+    /// is mapped in `helpers`.
+    pub range_helper: Option<String>,
+    /// Re-indent lines inside blocks by this many spaces per nesting level,
+    /// relative to the line holding the block's opening tag. Deeper lines
+    /// keep their indentation relative to the first line of the block.
+    pub indent: Option<usize>,
+    /// Drop `{{! }}`, `{{!-- --}}` and `{{#comment}}` blocks from the output,
+    /// along with the line they sit on when nothing else shares it.
+    pub strip_comments: bool,
+    /// Copy a leading UTF-8 byte order mark to the output instead of
+    /// dropping it.
+    pub keep_bom: bool,
+    /// Copy empty `{{}}` tags to the output instead of removing them; they
+    /// are reported either way.
+    ///
+    /// ```
+    /// use sline_transpiler::{Code, Level, Options, transpile};
+    ///
+    /// let (output, diagnostics) = transpile("a{{}}b{{ }}c{{{}}}d", &Options::default());
+    /// assert_eq!(output, "abcd");
+    /// assert_eq!(diagnostics.len(), 3);
+    /// assert!(diagnostics.iter().all(|diagnostic| diagnostic.code == Code::EmptyTag
+    ///     && diagnostic.level == Level::Warning));
+    ///
+    /// let options = Options {
+    ///     keep_empty_tags: true,
+    ///     ..Options::default()
+    /// };
+    /// assert_eq!(transpile("a{{}}b", &options).0, "a{{  }}b");
+    /// ```
+    pub keep_empty_tags: bool,
+    /// What `{{#comment}}...{{/comment}}` blocks become.
+    pub comment_style: CommentStyle,
+    /// How tags are padded inside their braces, unless `preserve_spacing`
+    /// keeps them as written.
+    pub mustache_spacing: MustacheSpacing,
+    /// Rewrite mustaches written with HTML brace entities in the copied
+    /// text, such as `&#123;&#123;name&#125;&#125;`, as `{{name}}` inside a
+    /// Sline `raw` block, so they still display as template syntax.
+    pub normalize_escaped_braces: bool,
+    /// Remove the indentation and line break around block tags, `else` and
+    /// comments that stand alone on their line, as Handlebars does when
+    /// rendering, so the Sline output renders without the extra blank lines.
+    pub standalone_whitespace: bool,
+    /// Report helpers that are neither built in nor in `helpers` as errors
+    /// rather than warnings.
+    pub fail_unknown_helpers: bool,
+    /// Match the `each`, `if`, `unless`, `with` and `else` keywords in any
+    /// case, lowercasing them with a warning. Identifiers keep their case.
+    ///
+    /// ```
+    /// use sline_transpiler::{Code, Options, transpile};
+    ///
+    /// let options = Options {
+    ///     case_insensitive_blocks: true,
+    ///     ..Options::default()
+    /// };
+    /// let (output, diagnostics) = transpile("{{#EACH Items}}{{#If this.On}}x{{/If}}{{/EACH}}", &options);
+    /// assert_eq!(output, "{{ #for item in Items }}{{ #if item.On }}x{{ /if }}{{ /for }}");
+    /// assert_eq!(diagnostics.len(), 4);
+    /// assert!(diagnostics.iter().all(|diagnostic| diagnostic.code == Code::KeywordCase));
+    /// ```
+    pub case_insensitive_blocks: bool,
+    /// Report every `{{{triple}}}` and `{{&name}}` tag at this level, for
+    /// reviewing the places raw HTML reaches the output.
+    ///
+    /// ```
+    /// use sline_transpiler::{Code, Level, Options, transpile};
+    ///
+    /// let options = Options {
+    ///     unescaped: Some(Level::Error),
+    ///     ..Options::default()
+    /// };
+    /// let (output, diagnostics) = transpile("{{{bio}}} {{name}} {{{footer}}}", &options);
+    /// assert_eq!(output, "{{{ bio }}} {{ name }} {{{ footer }}}");
+    /// assert_eq!(diagnostics.len(), 2);
+    /// assert!(diagnostics.iter().all(|diagnostic| diagnostic.code == Code::UnescapedOutput
+    ///     && diagnostic.level == Level::Error));
+    /// ```
+    pub unescaped: Option<Level>,
+    /// Block helpers whose opening and closing tags are kept as written,
+    /// arguments included, while the tags inside them are still converted.
+    pub passthrough_blocks: Vec<String>,
+    /// Project-specific rewriting tried on every expression before the
+    /// built-in conversion.
+    pub expression_hook: Option<ExpressionHook>,
+}
+
+/// A custom rewrite for [`Options::expression_hook`]. It is called with
+/// each expression, trimmed, such as `this.name` or `format price "usd"`;
+/// when it returns `Some`, that text is used as-is and the built-in
+/// conversion of the expression is skipped.
+///
+/// ```
+/// use sline_transpiler::{ExpressionHook, Options, transpile};
+///
+/// let options = Options {
+///     expression_hook: Some(ExpressionHook::new(|expression| {
+///         (expression == "this").then(|| "current".to_string())
+///     })),
+///     ..Options::default()
+/// };
+/// let (output, diagnostics) = transpile("{{this}} {{this.name}}", &options);
+/// assert_eq!(output, "{{ current }} {{ name }}");
+/// assert!(diagnostics.is_empty());
+/// ```
+#[derive(Clone)]
+pub struct ExpressionHook(Arc<HookFn>);
+
+type HookFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+impl ExpressionHook {
+    pub fn new(hook: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        ExpressionHook(Arc::new(hook))
+    }
+}
+
+impl std::fmt::Debug for ExpressionHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ExpressionHook(..)")
+    }
+}
+
+/// Output form for `{{#comment}}` blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommentStyle {
+    /// A `{{!-- ... --}}` comment.
+    #[default]
+    Handlebars,
+    /// The block kept as-is, since Sline has a `#comment` tag of its own.
+    Sline,
+    /// Dropped from the output, like with [`Options::strip_comments`].
+    Strip,
+}
+
+/// How reformatted tags pad the expression inside their braces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MustacheSpacing {
+    /// One space on each side: `{{ x }}` and `{{ upper x }}`.
+    #[default]
+    Both,
+    /// No spaces: `{{x}}` and `{{upper x}}`.
+    None,
+    /// Spaces only around expressions of several tokens: `{{x}}` but
+    /// `{{ upper x }}`.
+    HelpersOnly,
+}
+
+impl MustacheSpacing {
+    /// The padding to put on each side of `expression`.
+    fn padding(self, expression: &str) -> &'static str {
+        match self {
+            MustacheSpacing::Both => " ",
+            MustacheSpacing::None => "",
+            MustacheSpacing::HelpersOnly if split_arguments(expression).len() > 1 => " ",
+            MustacheSpacing::HelpersOnly => "",
+        }
+    }
+}
+
+/// What to do with the line breaks at the end of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FinalNewline {
+    /// Leave them as the input had them.
+    #[default]
+    Keep,
+    /// End non-empty output with exactly one line break.
+    Ensure,
+    /// Remove them.
+    Strip,
+}
+
+impl FinalNewline {
+    /// Applies the policy to `output`. A line break added by
+    /// [`FinalNewline::Ensure`] matches the last one in `output`, `\r\n` or
+    /// `\n`.
+    ///
+    /// ```
+    /// use sline_transpiler::FinalNewline;
+    ///
+    /// for (policy, outputs) in [
+    ///     (FinalNewline::Keep, ["a", "a\n", "a\n\n"]),
+    ///     (FinalNewline::Ensure, ["a\n", "a\n", "a\n"]),
+    ///     (FinalNewline::Strip, ["a", "a", "a"]),
+    /// ] {
+    ///     for (input, expected) in ["a", "a\n", "a\n\n"].into_iter().zip(outputs) {
+    ///         let mut output = input.to_string();
+    ///         policy.apply(&mut output);
+    ///         assert_eq!(output, expected);
+    ///     }
+    /// }
+    ///
+    /// let mut output = "a\r\nb\r\n\r\n".to_string();
+    /// FinalNewline::Ensure.apply(&mut output);
+    /// assert_eq!(output, "a\r\nb\r\n");
+    /// ```
+    pub fn apply(self, output: &mut String) {
+        if self == FinalNewline::Keep {
+            return;
+        }
+        let line_break = match output.rfind('\n') {
+            Some(end) if output[..end].ends_with('\r') => "\r\n",
+            _ => "\n",
+        };
+        output.truncate(output.trim_end_matches(['\r', '\n']).len());
+        if self == FinalNewline::Ensure && !output.is_empty() {
+            output.push_str(line_break);
+        }
+    }
+}
+
+const BOM: char = '\u{FEFF}';
+
+#[derive(Debug, Clone)]
+enum BlockContext {
+    Each {
+        alias: String,
+        index: Option<String>,
+        /// Whether `index` is a `key` binding added by `each_as_object`
+        /// rather than a block param.
+        object: bool,
+        /// Set once `{{else}}` is reached, where the loop binding is gone.
+        empty: bool,
+        /// Whether `@key` has been converted to `index`, which then holds
+        /// the key, and whether `@index` has.
+        key_used: Cell<bool>,
+        index_used: Cell<bool>,
+        open: Span,
+    },
+    If {
+        open: Span,
+    },
+    Unless {
+        open: Span,
+    },
+    With {
+        expr: Option<String>,
+        alias: Option<String>,
+        open: Span,
+    },
+    Helper {
+        name: String,
+        mapped: String,
+        open: Span,
+    },
+    Inline {
+        open: Span,
+    },
+    /// A `{{#name}}` block that isn't a known helper, such as a Mustache
+    /// section. `converted` is set when it became an `#if`.
+    Section {
+        name: String,
+        converted: bool,
+        open: Span,
+    },
+}
+
+impl BlockContext {
+    fn name(&self) -> &str {
+        match self {
+            BlockContext::Each { .. } => "each",
+            BlockContext::If { .. } => "if",
+            BlockContext::Unless { .. } => "unless",
+            BlockContext::With { .. } => "with",
+            BlockContext::Helper { name, .. } => name,
+            BlockContext::Inline { .. } => "inline",
+            BlockContext::Section { name, .. } => name,
+        }
+    }
+
+    fn open(&self) -> Span {
+        match self {
+            BlockContext::Each { open, .. }
+            | BlockContext::If { open }
+            | BlockContext::Unless { open }
+            | BlockContext::With { open, .. }
+            | BlockContext::Helper { open, .. }
+            | BlockContext::Inline { open }
+            | BlockContext::Section { open, .. } => *open,
+        }
+    }
+
+    fn is_scope(&self) -> bool {
+        matches!(
+            self,
+            BlockContext::Each { empty: false, .. } | BlockContext::With { .. }
+        )
+    }
+}
+
+const BUILTIN_HELPERS: &[&str] = &["if", "unless", "each", "with", "lookup", "log"];
+
+/// Comparison helpers written as Sline operators, unless they are mapped
+/// with `helpers`.
+const COMPARISON_HELPERS: &[(&str, &str)] = &[
+    ("eq", "=="),
+    ("ne", "!="),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("lte", "<="),
+    ("gte", ">="),
+];
+
+/// Logical helpers written as Sline operators like [`COMPARISON_HELPERS`].
+/// They take two or more arguments; `not` takes one and becomes `!`.
+const LOGICAL_HELPERS: &[(&str, &str)] = &[("and", "&&"), ("or", "||")];
+
+/// Debugging helpers that produce no output and are dropped unless mapped.
+const DROPPED_HELPERS: &[&str] = &["log", "debugger"];
+
+/// How a Handlebars construct comes out of [`transpile`] with the default
+/// [`Options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// Rewritten into its Sline equivalent.
+    Converted,
+    /// Rewritten, but with a warning asking for a manual check.
+    Warned,
+    /// Left as-is, with a diagnostic.
+    Unsupported,
+}
+
+impl Support {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Support::Converted => "converted",
+            Support::Warned => "warned",
+            Support::Unsupported => "unsupported",
+        }
+    }
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A Handlebars construct and what the transpiler does with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// The construct as it appears in a template.
+    pub construct: String,
+    pub support: Support,
+    /// What it becomes, or why it is not converted.
+    pub note: String,
+    /// The diagnostic reported for it, if any.
+    pub code: Option<Code>,
+    /// For a converted construct, a template using it and the output
+    /// [`transpile`] gives for it with the default [`Options`].
+    pub example: Option<Example>,
+}
+
+/// A template and what it converts to, for [`Feature::example`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Example {
+    pub input: String,
+    pub output: String,
+}
+
+impl Feature {
+    fn example(mut self, input: &str, output: &str) -> Self {
+        self.example = Some(Example {
+            input: input.to_string(),
+            output: output.to_string(),
+        });
+        self
+    }
+}
+
+/// Every Handlebars construct the transpiler recognizes, in the order
+/// `transform_tag` and the expression rewriting check for them. Helper
+/// entries come from the same tables the conversion uses.
+///
+/// ```
+/// use sline_transpiler::{Support, features};
+///
+/// let each = features().into_iter().find(|feature| feature.construct == "{{#each}}");
+/// assert_eq!(each.unwrap().support, Support::Converted);
+/// ```
+pub fn features() -> Vec<Feature> {
+    let feature = |construct: &str, support, note: &str, code| Feature {
+        construct: construct.to_string(),
+        support,
+        note: note.to_string(),
+        code,
+        example: None,
+    };
+    let mut features = vec![
+        feature(
+            "{{#*inline}}",
+            Support::Unsupported,
+            "no Sline equivalent; move it to its own partial file",
+            Some(Code::InlinePartial),
+        ),
+        feature(
+            "{{*decorator}}",
+            Support::Unsupported,
+            "no Sline equivalent",
+            Some(Code::Decorator),
+        ),
+        feature(
+            "{{#each}}",
+            Support::Converted,
+            "#for, with block params as the loop variables",
+            None,
+        )
+        .example(
+            "{{#each items as |item i|}}{{i}}{{/each}}",
+            "{{ #for item, i in items }}{{ i }}{{ /for }}",
+        ),
+        feature(
+            "{{#each}} hash arguments",
+            Support::Warned,
+            "dropped from the loop header",
+            Some(Code::DroppedHashArguments),
+        ),
+        feature("{{#if}}", Support::Converted, "#if", None)
+            .example("{{#if a}}x{{/if}}", "{{ #if a }}x{{ /if }}"),
+        feature("{{#unless}}", Support::Converted, "negated #if", None)
+            .example("{{#unless a}}x{{/unless}}", "{{ #if !(a) }}x{{ /if }}"),
+        feature(
+            "{{else}} inside {{#unless}}",
+            Support::Warned,
+            "else of the negated #if",
+            Some(Code::UnlessElse),
+        ),
+        feature(
+            "{{else}}, {{^}}",
+            Support::Converted,
+            "else, also inside #for",
+            None,
+        )
+        .example(
+            "{{#if a}}x{{^}}y{{/if}}",
+            "{{ #if a }}x{{ else }}y{{ /if }}",
+        ),
+        feature(
+            "{{else if}}, {{else unless}}",
+            Support::Converted,
+            "else if, with the condition negated for unless",
+            None,
+        )
+        .example(
+            "{{#if a}}x{{else unless b}}y{{/if}}",
+            "{{ #if a }}x{{ else if !(b) }}y{{ /if }}",
+        ),
+        feature(
+            "{{else if}} inside {{#each}}",
+            Support::Unsupported,
+            "no Sline equivalent",
+            Some(Code::ChainedElseInEach),
+        ),
+        feature(
+            "{{#with path}}",
+            Support::Converted,
+            "#if, with paths inside the block rewritten",
+            None,
+        )
+        .example(
+            "{{#with user}}{{name}}{{/with}}",
+            "{{ #if user }}{{ user.name }}{{ /if }}",
+        ),
+        feature(
+            "{{#with (subexpression)}}",
+            Support::Unsupported,
+            "no path to rewrite the block against",
+            Some(Code::UnconvertedWith),
+        ),
+        feature(
+            "{{#section}}, {{^section}}",
+            Support::Warned,
+            "#if or negated #if; lists need #for instead",
+            Some(Code::SectionAsIf),
+        ),
+        feature(
+            "{{#comment}}, {{! }}, {{!-- --}}",
+            Support::Converted,
+            "{{!-- --}} for {{#comment}}; the others are kept",
+            None,
+        )
+        .example("{{#comment}} x {{/comment}}", "{{!-- x --}}"),
+        feature("{{{{raw}}}}", Support::Converted, "#raw", None)
+            .example("{{{{raw}}}}{{x}}{{{{/raw}}}}", "{{ #raw }}{{x}}{{ /raw }}"),
+        feature("{{{triple}}}", Support::Converted, "{{{ }}}", None)
+            .example("{{{html}}}", "{{{ html }}}"),
+        feature(
+            "{{> partial}}",
+            Support::Converted,
+            "{{ include \"partial\" }}",
+            None,
+        )
+        .example("{{> header}}", "{{ include \"header\" }}"),
+        feature(
+            "{{> (dynamic)}}",
+            Support::Unsupported,
+            "the partial name is only known at render time",
+            Some(Code::DynamicPartial),
+        ),
+        feature(
+            "this, ./path",
+            Support::Converted,
+            "the enclosing loop variable",
+            None,
+        )
+        .example(
+            "{{#each items}}{{this}} {{./name}}{{/each}}",
+            "{{ #for item in items }}{{ item }} {{ item.name }}{{ /for }}",
+        ),
+        feature(
+            "../path",
+            Support::Unsupported,
+            "converted to the enclosing scope with --allow-parent",
+            Some(Code::ParentScope),
+        ),
+        feature("@root.path", Support::Converted, "path", None)
+            .example("{{@root.title}}", "{{ title }}"),
+        feature(
+            "@index, @first, @last",
+            Support::Converted,
+            "forloop.index0, forloop.first, forloop.last",
+            None,
+        )
+        .example(
+            "{{#each items}}{{@index}} {{@first}} {{@last}}{{/each}}",
+            "{{ #for item in items }}{{ forloop.index0 }} {{ forloop.first }} {{ forloop.last }}{{ /for }}",
+        ),
+        feature(
+            "@key",
+            Support::Warned,
+            "the loop's key binding, named with `as |value key|` or --each-as-object",
+            Some(Code::MissingKeyBinding),
+        ),
+        feature(
+            "@../index, @../key",
+            Support::Warned,
+            "the index or key block param of the enclosing loop, one loop up per ../",
+            Some(Code::ParentDataVariable),
+        ),
+        feature(
+            "lookup",
+            Support::Converted,
+            "obj[key], or left as-is when it cannot be written as an index",
+            Some(Code::UnconvertedLookup),
+        )
+        .example("{{lookup user key}}", "{{ user[key] }}"),
+    ];
+    for (helper, operator) in COMPARISON_HELPERS.iter().chain(LOGICAL_HELPERS) {
+        let note = format!("a {operator} b");
+        features.push(
+            feature(&format!("({helper} a b)"), Support::Converted, &note, None).example(
+                &format!("{{{{#if ({helper} a b)}}}}{{{{/if}}}}"),
+                &format!("{{{{ #if {note} }}}}{{{{ /if }}}}"),
+            ),
+        );
+    }
+    features.push(
+        feature("(not a)", Support::Converted, "!a", None)
+            .example("{{#if (not a)}}{{/if}}", "{{ #if !a }}{{ /if }}"),
+    );
+    for helper in DROPPED_HELPERS {
+        features.push(feature(
+            helper,
+            Support::Warned,
+            "removed from the output",
+            Some(Code::DroppedHelper),
+        ));
+    }
+    features.push(feature(
+        "other helpers",
+        Support::Warned,
+        "kept by name; map them with --helpers",
+        Some(Code::UnknownHelper),
+    ));
+    features
+}
+
+/// Converts a Handlebars template into Sline, returning the output together
+/// with any diagnostics found along the way, ordered by position.
+///
+/// ```
+/// use sline_transpiler::{Options, transpile};
+///
+/// let (output, diagnostics) = transpile("{{#each items}}{{this}}{{/each}}", &Options::default());
+/// assert_eq!(output, "{{ #for item in items }}{{ item }}{{ /for }}");
+/// assert!(diagnostics.is_empty());
+/// ```
+///
+/// A `{{` that is never closed is reported as an error and copied as-is,
+/// and the tags after it are still converted:
+///
+/// ```
+/// use sline_transpiler::{Code, Level, Options, transpile};
+///
+/// let (output, diagnostics) = transpile("{{ name } and {{this.price}}", &Options::default());
+/// assert_eq!(output, "{{ name } and {{ price }}");
+/// assert_eq!(diagnostics[0].code, Code::UnterminatedTag);
+/// assert_eq!(diagnostics[0].level, Level::Error);
+/// assert_eq!(diagnostics[0].message, "Unterminated mustache at offset 0");
+/// ```
+///
+/// Literal conditions are kept as written, even inside a loop:
+///
+/// ```
+/// use sline_transpiler::{Options, transpile};
+///
+/// let input = r#"{{#each items}}{{#if "x"}}a{{/if}}{{#if 0}}b{{/if}}{{#unless true}}c{{/unless}}{{/each}}"#;
+/// let (output, diagnostics) = transpile(input, &Options::default());
+/// assert_eq!(
+///     output,
+///     r#"{{ #for item in items }}{{ #if "x" }}a{{ /if }}{{ #if 0 }}b{{ /if }}{{ #if !(true) }}c{{ /if }}{{ /for }}"#
+/// );
+/// assert!(diagnostics.is_empty());
+/// ```
+pub fn transpile(input: &str, options: &Options) -> (String, Vec<Diagnostic>) {
+    let mut output = String::new();
+    let diagnostics = transpile_into(input, options, &mut output);
+    (output, diagnostics)
+}
+
+/// Reads the template at `path` and transpiles it with [`transpile`].
+/// Fails if `path` is a directory or does not hold UTF-8 text.
+///
+/// ```
+/// use std::path::Path;
+/// use sline_transpiler::{Options, transpile_file};
+///
+/// let (output, diagnostics) =
+///     transpile_file(Path::new("example/test.html"), &Options::default())?;
+/// assert_eq!(output, std::fs::read_to_string("example/test-converted.html")?);
+/// assert!(diagnostics.is_empty());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "fs")]
+pub fn transpile_file(path: &Path, options: &Options) -> io::Result<(String, Vec<Diagnostic>)> {
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    let input = fs::read_to_string(path)?;
+    Ok(transpile(&input, options))
+}
+
+/// Like [`transpile`], but writes the output straight into `out`,
+/// replacing its contents, so callers converting many templates can reuse
+/// one output buffer. A [`Transpiler`] also reuses the converter's other
+/// buffers, and allocates less.
+///
+/// ```
+/// use sline_transpiler::{Options, transpile_into};
+///
+/// let mut output = String::new();
+/// for template in ["{{this.a}}", "{{this.b}}"] {
+///     transpile_into(template, &Options::default(), &mut output);
+/// }
+/// assert_eq!(output, "{{ b }}");
+/// ```
+pub fn transpile_into(input: &str, options: &Options, out: &mut String) -> Vec<Diagnostic> {
+    let mut converter = Converter::new(input, options, Some(std::mem::take(out)));
+    converter.convert(input, &LineIndex::new(input), options, None);
+    *out = converter.output.take_text();
+    converter.diagnostics
+}
+
+/// Converts `input` with the default options, plus `allow_parent`, and
+/// returns the output and diagnostics as one JSON object. It needs no file
+/// system access, so embedders such as a WebAssembly playground can pass
+/// plain strings in and out.
+///
+/// ```
+/// use sline_transpiler::transpile_string;
+///
+/// assert_eq!(
+///     transpile_string("{{foo}}", false),
+///     r#"{"output":"{{ foo }}","diagnostics":[]}"#
+/// );
+/// assert_eq!(
+///     transpile_string("{{../a}}", false),
+///     r#"{"output":"{{ ../a }}","diagnostics":[{"level":"error","code":"SL001","message":"Parent scope access (../) is not supported in Sline","line":1,"column":1}]}"#
+/// );
+/// ```
+pub fn transpile_string(input: &str, allow_parent: bool) -> String {
+    let options = Options {
+        allow_parent,
+        ..Options::default()
+    };
+    let (output, diagnostics) = transpile(input, &options);
+    let diagnostics: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let level = match diagnostic.level {
+                Level::Warning => "warning",
+                Level::Error => "error",
+            };
+            format!(
+                "{{\"level\":\"{level}\",\"code\":\"{}\",\"message\":{},\"line\":{},\"column\":{}}}",
+                diagnostic.code,
+                json_string(&diagnostic.message),
+                diagnostic.span.line,
+                diagnostic.span.column
+            )
+        })
+        .collect();
+    format!(
+        "{{\"output\":{},\"diagnostics\":[{}]}}",
+        json_string(&output),
+        diagnostics.join(",")
+    )
+}
+
+/// Quotes `value` as a JSON string.
+///
+/// ```
+/// assert_eq!(sline_transpiler::json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+/// ```
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32));
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Counts gathered while transpiling a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// Tags rewritten into Sline, including converted comment and raw
+    /// blocks. Tags passed through verbatim are not counted.
+    pub tags_transformed: usize,
+    /// `{{#each}}` blocks.
+    pub each_blocks: usize,
+    /// `{{#if}}` blocks.
+    pub if_blocks: usize,
+    /// `{{#unless}}` blocks.
+    pub unless_blocks: usize,
+    /// `{{#with}}` blocks.
+    pub with_blocks: usize,
+    /// `{{> partial}}` and `{{#> partial}}` invocations.
+    pub partials: usize,
+    /// `{{{triple}}}` and `{{&name}}` tags.
+    pub unescaped: usize,
+    /// References that climb with `../`, including `@../index`.
+    pub parent_references: usize,
+}
+
+impl Stats {
+    /// The construct counts, named for reports such as a migration plan.
+    ///
+    /// ```
+    /// use sline_transpiler::{Options, transpile_with_stats};
+    ///
+    /// let input = "{{#each items}}{{#if ../on}}{{{this.html}}}{{/if}}{{> row}}{{/each}}\
+    ///              {{#unless done}}{{#with user}}{{name}}{{/with}}{{/unless}}";
+    /// let (_, _, stats) = transpile_with_stats(input, &Options::default());
+    /// assert_eq!(
+    ///     stats.constructs(),
+    ///     [("each", 1), ("if", 1), ("unless", 1), ("with", 1), ("partial", 1), ("unescaped", 1), ("parent", 1)]
+    /// );
+    /// ```
+    pub fn constructs(&self) -> [(&'static str, usize); 7] {
+        [
+            ("each", self.each_blocks),
+            ("if", self.if_blocks),
+            ("unless", self.unless_blocks),
+            ("with", self.with_blocks),
+            ("partial", self.partials),
+            ("unescaped", self.unescaped),
+            ("parent", self.parent_references),
+        ]
+    }
+
+    /// Tallies the constructs in `tag`, the trimmed content of a mustache.
+    fn count(&mut self, tag: &str, triple: bool) {
+        let name = block_name(tag);
+        match name {
+            "#each" => self.each_blocks += 1,
+            "#if" => self.if_blocks += 1,
+            "#unless" => self.unless_blocks += 1,
+            "#with" => self.with_blocks += 1,
+            _ if tag.starts_with('>') || tag.starts_with("#>") => self.partials += 1,
+            _ => {}
+        }
+        if triple {
+            self.unescaped += 1;
+        }
+        self.parent_references += tag
+            .split(|character: char| character.is_whitespace() || matches!(character, '(' | '='))
+            .filter(|word| word.starts_with("../") || word.starts_with("@../"))
+            .count();
+    }
+}
+
+/// Like [`transpile`], but also reports [`Stats`] about the conversion.
+pub fn transpile_with_stats(input: &str, options: &Options) -> (String, Vec<Diagnostic>, Stats) {
+    let mut converter = Converter::new(input, options, Some(String::new()));
+    converter.convert(input, &LineIndex::new(input), options, None);
+    (
+        converter.output.take_text(),
+        converter.diagnostics,
+        converter.stats,
+    )
+}
+
+/// A piece of the output, mapped back to the input it was produced from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    /// Byte range in the input.
+    pub source: Range<usize>,
+    /// Byte range in the concatenated output.
+    pub output: Range<usize>,
+    /// Whether the text was produced by a transformed tag rather than
+    /// copied from the input (re-indented when [`Options::indent`] is set).
+    pub tag: bool,
+}
+
+/// Like [`transpile`], but returns the output as [`Segment`]s so tools can
+/// map each part of it back to the source.
+///
+/// ```
+/// use sline_transpiler::{Options, transpile_detailed};
+///
+/// let (segments, _) = transpile_detailed("Hi {{name}}", &Options::default());
+/// assert_eq!(segments[1].text, "{{ name }}");
+/// assert_eq!(segments[1].source, 3..11);
+/// assert!(segments[1].tag);
+/// ```
+pub fn transpile_detailed(input: &str, options: &Options) -> (Vec<Segment>, Vec<Diagnostic>) {
+    let (segments, diagnostics, _) = transpile_detailed_with_stats(input, options);
+    (segments, diagnostics)
+}
+
+/// Joins `segments` back into the output with a `{{!-- CODE: message --}}`
+/// comment after the part of it each diagnostic was reported on.
+/// Diagnostics without a location in the source are left out.
+///
+/// ```
+/// use sline_transpiler::{Options, annotate, transpile_detailed};
+///
+/// let (segments, diagnostics) = transpile_detailed("{{foo x}}!", &Options::default());
+/// let annotated = annotate(&segments, &diagnostics);
+/// assert_eq!(annotated, "{{ foo x }} {{!-- SL022: Unknown helper: foo --}}!");
+/// ```
+pub fn annotate(segments: &[Segment], diagnostics: &[Diagnostic]) -> String {
+    let mut notes: Vec<Vec<&Diagnostic>> = vec![Vec::new(); segments.len()];
+    for diagnostic in diagnostics {
+        if diagnostic.span.line == 0 {
+            continue;
+        }
+        let start = diagnostic.span.start;
+        let index = segments
+            .iter()
+            .position(|segment| segment.source.contains(&start))
+            .or_else(|| {
+                segments
+                    .iter()
+                    .rposition(|segment| segment.source.start <= start)
+            });
+        if let Some(index) = index {
+            notes[index].push(diagnostic);
+        }
+    }
+
+    let mut output = String::new();
+    for (segment, notes) in segments.iter().zip(notes) {
+        output.push_str(&segment.text);
+        for diagnostic in notes {
+            output.push_str(&format!(
+                " {{{{!-- {}: {} --}}}}",
+                diagnostic.code.as_str(),
+                diagnostic.message.replace("--}}", "-- }}")
+            ));
+        }
+    }
+    output
+}
+
+#[derive(Default)]
+struct SegmentWriter {
+    segments: Vec<Segment>,
+    len: usize,
+    indenter: Option<Indenter>,
+    /// Input ranges dropped from the text copied by the next `between`.
+    skipped: Vec<Range<usize>>,
+    /// Whether anything but whitespace has been written.
+    visible: bool,
+    /// Emptied texts of the segments before a [`reset`](Self::reset), reused
+    /// for new literal segments.
+    spare: Vec<String>,
+    /// The raw block tags wrapped around entity-escaped mustaches in the
+    /// copied text, when [`Options::normalize_escaped_braces`] is set.
+    escaped_braces: Option<(String, String)>,
+    /// For callers that only need the text, the output written straight
+    /// into one string instead of being kept as segments.
+    text: Option<String>,
+}
+
+impl SegmentWriter {
+    /// A writer keeping segments, or with `text`, writing into it instead.
+    fn new(options: &Options, text: Option<String>) -> Self {
+        SegmentWriter {
+            indenter: Indenter::new(options),
+            escaped_braces: options.normalize_escaped_braces.then(|| raw_tags(options)),
+            text: text.map(|mut text| {
+                text.clear();
+                text
+            }),
+            ..SegmentWriter::default()
+        }
+    }
+
+    /// The text written when the writer was made with a string to write to.
+    fn take_text(&mut self) -> String {
+        self.text.take().unwrap_or_default()
+    }
+
+    /// An empty string for a new segment, reusing a spare one if any.
+    fn buffer(&mut self) -> String {
+        self.spare.pop().unwrap_or_default()
+    }
+
+    /// Empties the writer for another template, keeping its buffers.
+    fn reset(&mut self, options: &Options) {
+        for segment in self.segments.drain(..) {
+            let mut text = segment.text;
+            text.clear();
+            self.spare.push(text);
+        }
+        self.len = 0;
+        self.indenter = Indenter::new(options);
+        self.escaped_braces = options.normalize_escaped_braces.then(|| raw_tags(options));
+        self.skipped.clear();
+        self.visible = false;
+        if let Some(ref mut text) = self.text {
+            text.clear();
+        }
+    }
+
+    /// Copies the template text between two tags, re-indenting it when
+    /// enabled. `closes` tells whether the following tag closes a block
+    /// (or is an `else`), which puts that line at the opener's level.
+    fn between(&mut self, input: &str, source: Range<usize>, closes: bool) {
+        let mut pieces = Vec::with_capacity(self.skipped.len() + 1);
+        let mut start = source.start;
+        for skipped in self.skipped.drain(..) {
+            pieces.push(start..skipped.start);
+            start = skipped.end;
+        }
+        pieces.push(start..source.end);
+
+        match self.indenter {
+            // The text around dropped tags is re-indented as a whole, so
+            // lines keep their place even when a tag was removed from them.
+            Some(ref mut indenter) => {
+                let mut text: String = pieces.into_iter().map(|piece| &input[piece]).collect();
+                if let Some(ref raw) = self.escaped_braces
+                    && let Some(normalized) = normalize_escaped_braces(&text, raw)
+                {
+                    text = normalized;
+                }
+                let text = indenter.reindent(&text, closes);
+                self.push_literal(&text, source);
+            }
+            None => {
+                for piece in pieces {
+                    let normalized = self
+                        .escaped_braces
+                        .as_ref()
+                        .and_then(|raw| normalize_escaped_braces(&input[piece.clone()], raw));
+                    match normalized {
+                        Some(text) => self.push_literal(&text, piece),
+                        None => self.literal(input, piece),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops `source` from the text copied by the next [`between`](Self::between).
+    fn skip(&mut self, source: Range<usize>) {
+        self.skipped.push(source);
+    }
+
+    /// Records the block depth after a tag, so newly opened blocks
+    /// remember the indentation of the line they were opened on.
+    fn set_depth(&mut self, depth: usize) {
+        if let Some(ref mut indenter) = self.indenter {
+            indenter.openers.truncate(depth);
+            while indenter.openers.len() < depth {
+                indenter.openers.push(Opener {
+                    indent: indenter.line_indent,
+                    base: None,
+                });
+            }
+        }
+    }
+
+    /// Copies `input[source]` to the output unchanged.
+    fn literal(&mut self, input: &str, source: Range<usize>) {
+        let text = &input[source.clone()];
+        if let Some(ref mut indenter) = self.indenter {
+            indenter.observe(text);
+        }
+        self.push_literal(text, source);
+    }
+
+    /// Appends literal text, merging with a preceding literal segment when
+    /// the two are contiguous.
+    fn push_literal(&mut self, text: &str, source: Range<usize>) {
+        if source.is_empty() {
+            return;
+        }
+        self.visible |= !is_blank(text);
+        self.len += text.len();
+        if let Some(ref mut output) = self.text {
+            output.push_str(text);
+            return;
+        }
+        if let Some(last) = self.segments.last_mut()
+            && !last.tag
+            && last.source.end == source.start
+        {
+            last.text.push_str(text);
+            last.source.end = source.end;
+            last.output.end = self.len;
+            return;
+        }
+        let mut owned = self.buffer();
+        owned.push_str(text);
+        self.segments.push(Segment {
+            text: owned,
+            source,
+            output: self.len - text.len()..self.len,
+            tag: false,
+        });
+    }
+
+    fn tag(&mut self, mut text: String, source: Range<usize>) {
+        if let Some(ref mut indenter) = self.indenter {
+            indenter.observe(&text);
+        }
+        self.visible |= !is_blank(&text);
+        let start = self.len;
+        self.len += text.len();
+        if let Some(ref mut output) = self.text {
+            // The tag's buffer goes back to be reused for the next tag.
+            output.push_str(&text);
+            text.clear();
+            self.spare.push(text);
+            return;
+        }
+        self.segments.push(Segment {
+            text,
+            source,
+            output: start..self.len,
+            tag: true,
+        });
+    }
+}
+
+/// Re-indents template text by block depth for [`Options::indent`].
+struct Indenter {
+    width: usize,
+    openers: Vec<Opener>,
+    /// Indentation of the output line currently being written.
+    line_indent: usize,
+}
+
+impl Indenter {
+    fn new(options: &Options) -> Option<Self> {
+        options.indent.map(|width| Indenter {
+            width,
+            openers: Vec::new(),
+            line_indent: 0,
+        })
+    }
+}
+
+struct Opener {
+    /// Output indentation of the line holding the opening tag.
+    indent: usize,
+    /// Input indentation of the first line inside the block.
+    base: Option<usize>,
+}
+
+impl Indenter {
+    fn reindent(&mut self, text: &str, closes: bool) -> String {
+        let mut lines = text.split('\n');
+        let mut result = lines.next().unwrap_or_default().to_string();
+        let mut lines = lines.peekable();
+        while let Some(line) = lines.next() {
+            result.push('\n');
+            let content = line.trim_start_matches([' ', '\t']);
+            let original = line.len() - content.len();
+            let last = lines.peek().is_none();
+            let indent = match self.openers.last_mut() {
+                None => {
+                    result.push_str(line);
+                    self.line_indent = original;
+                    continue;
+                }
+                Some(_) if content.trim().is_empty() && !last => 0,
+                Some(opener) if content.is_empty() && closes => opener.indent,
+                Some(opener) => {
+                    let base = *opener.base.get_or_insert(original);
+                    opener.indent + self.width + original.saturating_sub(base)
+                }
+            };
+            result.extend(std::iter::repeat_n(' ', indent));
+            result.push_str(content);
+            self.line_indent = indent;
+        }
+        result
+    }
+
+    /// Tracks the indentation of text copied without re-indenting.
+    fn observe(&mut self, text: &str) {
+        if let Some((_, line)) = text.rsplit_once('\n') {
+            self.line_indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        }
+    }
+}
+
+/// Like [`transpile_detailed`], but also reports [`Stats`] about the
+/// conversion.
+pub fn transpile_detailed_with_stats(
+    input: &str,
+    options: &Options,
+) -> (Vec<Segment>, Vec<Diagnostic>, Stats) {
+    let mut converter = Converter::new(input, options, None);
+    converter.convert(input, &LineIndex::new(input), options, None);
+    (
+        converter.output.segments,
+        converter.diagnostics,
+        converter.stats,
+    )
+}
+
+/// Transpiles one template after another, such as on every edit in an
+/// editor, reusing the buffers of the previous call instead of allocating
+/// them again.
+///
+/// ```
+/// use sline_transpiler::{Options, Transpiler};
+///
+/// let options = Options::default();
+/// let mut transpiler = Transpiler::new();
+/// for template in ["{{this.a}}", "{{#each items}}{{this}}{{/each}}"] {
+///     let (output, diagnostics) = transpiler.transpile(template, &options);
+///     assert!(output.starts_with("{{ "));
+///     assert!(diagnostics.is_empty());
+/// }
+/// ```
+#[derive(Default)]
+pub struct Transpiler {
+    converter: Option<Converter>,
+}
+
+impl Transpiler {
+    pub fn new() -> Self {
+        Transpiler::default()
+    }
+
+    /// Like [`transpile`], but the output and diagnostics are borrowed
+    /// from buffers that the next call reuses.
+    pub fn transpile(&mut self, input: &str, options: &Options) -> (&str, &[Diagnostic]) {
+        let converter = match self.converter {
+            Some(ref mut converter) => {
+                converter.reset(input, options);
+                converter
+            }
+            None => self
+                .converter
+                .insert(Converter::new(input, options, Some(String::new()))),
+        };
+        converter.convert(input, &LineIndex::new(input), options, None);
+        let output = converter.output.text.as_deref().unwrap_or_default();
+        (output, &converter.diagnostics)
+    }
+}
+
+/// Converts a template that arrives in pieces, such as a large piped input,
+/// handing out each part of the output as soon as later text can no longer
+/// change it.
+///
+/// Only complete lines are converted, and a tag or block that is still
+/// open at the end of them waits for the rest of its text, so the result
+/// is the same as transpiling the whole input at once.
+///
+/// ```
+/// use sline_transpiler::{Options, Stream};
+///
+/// let options = Options::default();
+/// let mut stream = Stream::new(&options);
+/// let mut output = String::new();
+/// stream.feed("{{#each items}}\n  {{th", &mut output);
+/// assert_eq!(output, "{{ #for item in items }}");
+/// stream.feed("is}}\n{{/each}}\n", &mut output);
+/// let (diagnostics, _) = stream.finish(&mut output);
+/// assert_eq!(output, "{{ #for item in items }}\n  {{ item }}\n{{ /for }}\n");
+/// assert!(diagnostics.is_empty());
+/// ```
+pub struct Stream<'a> {
+    options: &'a Options,
+    converter: Option<Converter>,
+    /// Input not converted yet, always starting at the beginning of a line.
+    pending: String,
+    /// Byte offset and line count of the input before `pending`.
+    offset: usize,
+    lines: usize,
+}
+
+impl<'a> Stream<'a> {
+    pub fn new(options: &'a Options) -> Self {
+        Stream {
+            options,
+            converter: None,
+            pending: String::new(),
+            offset: 0,
+            lines: 0,
+        }
+    }
+
+    /// Adds `chunk` to the input and appends whatever output is final to
+    /// `out`.
+    pub fn feed(&mut self, chunk: &str, out: &mut String) {
+        self.pending.push_str(chunk);
+        if let Some(newline) = self.pending.rfind('\n') {
+            self.convert(Some(newline + 1), out);
+        }
+    }
+
+    /// Converts the rest of the input, appending it to `out`, and returns
+    /// the diagnostics and [`Stats`] for the whole template.
+    pub fn finish(mut self, out: &mut String) -> (Vec<Diagnostic>, Stats) {
+        self.convert(None, out);
+        let converter = self.converter.expect("converted above");
+        (converter.diagnostics, converter.stats)
+    }
+
+    fn convert(&mut self, limit: Option<usize>, out: &mut String) {
+        let input = self.pending.as_str();
+        let options = self.options;
+        let converter = self
+            .converter
+            .get_or_insert_with(|| Converter::new(input, options, None));
+        let lines = LineIndex::at(input, self.offset, self.lines);
+        converter.convert(input, &lines, options, limit);
+        for segment in converter.output.segments.drain(..) {
+            out.push_str(&segment.text);
+        }
+
+        // The line holding the first unconverted text is kept whole, so
+        // checks for tags standing alone on their line still see its start.
+        let done = input[..converter.index]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        self.offset += done;
+        self.lines += input[..done].matches('\n').count();
+        converter.rebase(done);
+        self.pending.drain(..done);
+    }
+}
+
+/// Scanning state, kept between calls so a [`Stream`] can convert its
+/// input a piece at a time.
+struct Converter {
+    output: SegmentWriter,
+    stack: Vec<BlockContext>,
+    diagnostics: Vec<Diagnostic>,
+    stats: Stats,
+    /// Input before this offset has been written to the output.
+    index: usize,
+    /// Where the search for the next tag resumes.
+    scan: usize,
+    /// Whether the input has anything but whitespace.
+    content: bool,
+}
+
+impl Converter {
+    /// A converter keeping the output as segments, or with `text`, writing
+    /// it straight into that string.
+    fn new(input: &str, options: &Options, text: Option<String>) -> Self {
+        let mut converter = Converter {
+            output: SegmentWriter::new(options, text),
+            stack: Vec::new(),
+            diagnostics: Vec::new(),
+            stats: Stats::default(),
+            index: 0,
+            scan: 0,
+            content: false,
+        };
+        converter.start(input, options);
+        converter
+    }
+
+    /// Empties the converter for another template, keeping its buffers.
+    fn reset(&mut self, input: &str, options: &Options) {
+        self.output.reset(options);
+        self.stack.clear();
+        self.diagnostics.clear();
+        self.stats = Stats::default();
+        self.content = false;
+        self.start(input, options);
+    }
+
+    /// Skips a leading byte order mark, copying it when it is kept.
+    fn start(&mut self, input: &str, options: &Options) {
+        let index = if input.starts_with(BOM) {
+            BOM.len_utf8()
+        } else {
+            0
+        };
+        if options.keep_bom {
+            self.output.literal(input, 0..index);
+        }
+        self.index = index;
+        self.scan = index;
+    }
+
+    /// Converts the tags in `input`. With a `limit`, more input is still to
+    /// come, so scanning stops at the first tag that does not end before it,
+    /// and the text after the last converted tag is left for the next call.
+    fn convert(&mut self, input: &str, lines: &LineIndex, options: &Options, limit: Option<usize>) {
+        let Converter {
+            output,
+            stack,
+            diagnostics,
+            stats,
+            index,
+            scan,
+            content,
+        } = self;
+        *content |= !is_blank(input);
+        let mut tokens = Tokens::new(input, *scan);
+        let mut block_closes = Closes::new(input);
+
+        loop {
+            *scan = tokens.index;
+            let Some(token) = tokens.next() else {
+                break;
+            };
+            if limit.is_some_and(|limit| !settled(&mut block_closes, &token, limit)) {
+                break;
+            }
+            if token.unterminated.is_some() {
+                // The braces are copied with the text around them.
+                if token.backslashes != 1 {
+                    diagnostics.push(unterminated_tag(&token, lines));
+                }
+                continue;
+            }
+            let token_trim = token.inner.trim();
+
+            let strip = options.strip_comments
+                || (options.comment_style == CommentStyle::Strip
+                    && token_trim.starts_with("#comment"));
+            if strip
+                && token.backslashes == 0
+                && !token.raw
+                && let Some(end) = comment_end(&mut block_closes, &token, token_trim)
+            {
+                let (keep, resume) =
+                    standalone_line(input, *index, token.start, end).unwrap_or((token.start, end));
+                output.skip(keep..resume);
+                tokens.seek(resume);
+                continue;
+            }
+
+            if token.backslashes == 0 && !token.raw && token_trim.is_empty() {
+                diagnostics.push(Diagnostic::new(
+                    Code::EmptyTag,
+                    "Empty mustache tag".to_string(),
+                    lines.span(token.start, token.end),
+                ));
+                if !options.keep_empty_tags {
+                    let (keep, resume) = standalone_line(input, *index, token.start, token.end)
+                        .unwrap_or((token.start, token.end));
+                    output.skip(keep..resume);
+                    tokens.seek(resume);
+                    continue;
+                }
+            }
+
+            if token.backslashes == 0
+                && !token.raw
+                && let Some(helper) = dropped_helper(token_trim, options)
+            {
+                diagnostics.push(Diagnostic::new(
+                    Code::DroppedHelper,
+                    format!("Removed {{{{{helper}}}}} call; it has no output in Sline"),
+                    lines.span(token.start, token.end),
+                ));
+                let (keep, resume) = standalone_line(input, *index, token.start, token.end)
+                    .unwrap_or((token.start, token.end));
+                output.skip(keep..resume);
+                tokens.seek(resume);
+                continue;
+            }
+
+            let standalone = if options.standalone_whitespace && standalone_kind(&token) {
+                standalone_line(input, *index, token.start, token.end)
+            } else {
+                None
+            };
+            if let Some((line_start, _)) = standalone {
+                output.skip(line_start..token.start);
+            }
+            let closes = closes(&token);
+            if token.backslashes > 0 {
+                output.between(input, *index..token.start - 1, closes);
+            } else {
+                output.between(input, *index..token.start, closes);
+            }
+            let span = lines.span(token.start, token.end);
+
+            if token.backslashes == 1 {
+                output.literal(input, token.start..token.end);
+                *index = token.end;
+                continue;
+            }
+
+            if token.raw {
+                if let Some(close_end) = block_closes.raw(token.end, token_trim) {
+                    let (open, close) = raw_tags(options);
+                    output.tag(open, token.start..token.end);
+                    output.literal(input, token.end..close_end.start);
+                    output.tag(close, close_end.start..close_end.end);
+                    stats.tags_transformed += 1;
+                    *index = close_end.end;
+                    tokens.seek(*index);
+                } else {
+                    diagnostics.push(Diagnostic::new(
+                        Code::UnclosedBlock,
+                        format!("Unclosed {{{{{{{{{token_trim}}}}}}}}} raw block"),
+                        span,
+                    ));
+                    output.literal(input, token.start..token.end);
+                    *index = token.end;
+                }
+                continue;
+            }
+
+            if token_trim.starts_with('!') {
+                output.literal(input, token.start..token.end);
+                if let Some((_, line_end)) = standalone {
+                    output.skip(token.end..line_end);
+                }
+                *index = token.end;
+                continue;
+            }
+
+            // Sline raw blocks, such as the ones converted from `{{{{raw}}}}`,
+            // are already in their final form.
+            if token_trim == "#raw"
+                && let Some(close_end) = block_closes.block(token.end, "raw")
+            {
+                output.literal(input, token.start..close_end.end);
+                *index = close_end.end;
+                tokens.seek(*index);
+                continue;
+            }
+
+            if token_trim.starts_with("#comment") {
+                if options.comment_style == CommentStyle::Sline
+                    && let Some(close_end) = block_closes.block(token.end, "comment")
+                {
+                    output.literal(input, token.start..close_end.end);
+                    *index = close_end.end;
+                    tokens.seek(*index);
+                    continue;
+                }
+                if let Some(close_end) = block_closes.block(token.end, "comment") {
+                    let open = if token.trim_left { "{{~!--" } else { "{{!--" };
+                    let close = if close_end.trim_right {
+                        "--~}}"
+                    } else {
+                        "--}}"
+                    };
+                    output.tag(open.to_string(), token.start..token.end);
+                    output.literal(input, token.end..close_end.start);
+                    output.tag(close.to_string(), close_end.start..close_end.end);
+                    stats.tags_transformed += 1;
+                    *index = close_end.end;
+                    tokens.seek(*index);
+                    continue;
+                } else {
+                    diagnostics.push(Diagnostic::new(
+                        Code::UnclosedBlock,
+                        "Unclosed {{#comment}} block".to_string(),
+                        span,
+                    ));
+                    output.literal(input, token.start..token.end);
+                    *index = token.end;
+                    continue;
+                }
+            }
+
+            // `{{&x}}` is the same unescaped output as `{{{x}}}`.
+            let (token_trim, triple) = match token_trim.strip_prefix('&') {
+                Some(rest) if !token.triple => (rest.trim_start(), true),
+                _ => (token_trim, token.triple),
+            };
+            if triple && let Some(level) = options.unescaped {
+                diagnostics.push(Diagnostic {
+                    level,
+                    ..Diagnostic::new(
+                        Code::UnescapedOutput,
+                        format!("Unescaped output of {token_trim}; it is not HTML-escaped"),
+                        span,
+                    )
+                });
+            }
+            // Tags split across lines are joined onto one when reformatting.
+            let joined;
+            let token_trim = if !options.preserve_spacing && token_trim.contains('\n') {
+                joined = collapse_whitespace(token_trim);
+                joined.as_str()
+            } else {
+                token_trim
+            };
+            let lowercased;
+            let token_trim = match lowercase_keyword(token_trim) {
+                Some(keyword) if options.case_insensitive_blocks => {
+                    diagnostics.push(Diagnostic::new(
+                        Code::KeywordCase,
+                        format!("Lowercased the keyword of {{{{{token_trim}}}}}"),
+                        span,
+                    ));
+                    lowercased = keyword;
+                    lowercased.as_str()
+                }
+                _ => token_trim,
+            };
+            stats.count(token_trim, triple);
+            let transformed = transform_tag(token_trim, span, stack, options, diagnostics);
+            let (leading, trailing) = if options.preserve_spacing {
+                (token.leading, token.trailing)
+            } else {
+                let pad = options.mustache_spacing.padding(&transformed);
+                (pad, pad)
+            };
+            let mut text = output.buffer();
+            text.reserve(transformed.len() + 8);
+            text.push_str(if triple { "{{{" } else { "{{" });
+            if token.trim_left {
+                text.push('~');
+            }
+            text.push_str(leading);
+            text.push_str(&transformed);
+            text.push_str(trailing);
+            if token.trim_right {
+                text.push('~');
+            }
+            text.push_str(if triple { "}}}" } else { "}}" });
+            output.tag(text, token.start..token.end);
+            if let Some((_, line_end)) = standalone {
+                output.skip(token.end..line_end);
+            }
+            output.set_depth(stack.len());
+            stats.tags_transformed += 1;
+            *index = token.end;
+        }
+
+        if limit.is_some() {
+            return;
+        }
+        output.between(input, *index..input.len(), false);
+
+        // Most likely every line was a comment dropped by `strip_comments`.
+        if *content && !output.visible {
+            diagnostics.push(Diagnostic::new(
+                Code::EmptyOutput,
+                "The input is not blank but the output is".to_string(),
+                Span::default(),
+            ));
+        }
+
+        for context in stack.drain(..) {
+            let open = context.open();
+            diagnostics.push(Diagnostic::new(
+                Code::UnclosedBlock,
+                format!(
+                    "Unclosed block: {} (opened at offset {})",
+                    context.name(),
+                    open.start
+                ),
+                open,
+            ));
+        }
+        // Unclosed blocks are only found at the end; the sort is stable, so
+        // diagnostics for the same tag keep the order they were found in.
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    }
+
+    /// Shifts the offsets into the input after `count` bytes are removed
+    /// from its start.
+    fn rebase(&mut self, count: usize) {
+        self.index -= count;
+        self.scan -= count;
+        for skipped in &mut self.output.skipped {
+            *skipped = skipped.start - count..skipped.end - count;
+        }
+    }
+}
+
+/// Whether `text` is only whitespace, counting a byte order mark as such.
+fn is_blank(text: &str) -> bool {
+    text.chars()
+        .all(|character| character.is_whitespace() || character == BOM)
+}
+
+/// Whether `token`, and any raw or comment block it opens, ends before
+/// `limit`, so that text arriving later cannot change how it converts.
+fn settled<'a>(block_closes: &mut Closes<'a>, token: &Token<'a>, limit: usize) -> bool {
+    let input = block_closes.source;
+    if let Some(decided) = token.unterminated {
+        return decided < limit;
+    }
+    if token.end > limit {
+        return false;
+    }
+    let end = if token.raw {
+        block_closes
+            .raw(token.end, token.inner.trim())
+            .map(|close| close.end)
+    } else if token.inner == "#raw" {
+        block_closes.block(token.end, "raw").map(|close| close.end)
+    } else if token.inner.starts_with("#comment") {
+        block_closes
+            .block(token.end, "comment")
+            .map(|close| close.end)
+    } else if !token.triple && token.inner.starts_with("!--") {
+        // Closed at a plain `}}` only while its `--}}` has not arrived.
+        long_comment_close(input, token.start).map(|_| token.end)
+    } else if token.inner.starts_with('!') {
+        Some(token.end)
+    } else {
+        // A close found by ignoring an unterminated quote may move once the
+        // rest of the quote arrives.
+        let (open_len, close_seq) = if token.triple { (3, "}}}") } else { (2, "}}") };
+        find_tag_close(input, token.start + open_len, close_seq).map(|_| token.end)
+    };
+    end.is_some_and(|end| end <= limit)
+}
+
+/// Replaces each run of whitespace outside quotes and `[...]` segments
+/// with a single space.
+fn collapse_whitespace(content: &str) -> String {
+    let mut collapsed = String::with_capacity(content.len());
+    let mut quote = None;
+    let mut pending = false;
+    for character in content.chars() {
+        if quote.is_none() && character.is_whitespace() {
+            pending = true;
+            continue;
+        }
+        if pending {
+            collapsed.push(' ');
+            pending = false;
+        }
+        match quote {
+            Some(close) if character == close => quote = None,
+            Some(_) => {}
+            None => match character {
+                '"' | '\'' => quote = Some(character),
+                '[' => quote = Some(']'),
+                _ => {}
+            },
+        }
+        collapsed.push(character);
+    }
+    collapsed
+}
+
+/// End offset of a comment starting at `token`, including the whole
+/// `{{#comment}}...{{/comment}}` block. `None` if it isn't a comment or
+/// the block is never closed.
+fn comment_end(block_closes: &mut Closes, token: &Token, token_trim: &str) -> Option<usize> {
+    if token_trim.starts_with('!') {
+        Some(token.end)
+    } else if token_trim.starts_with("#comment") {
+        block_closes
+            .block(token.end, "comment")
+            .map(|close| close.end)
+    } else {
+        None
+    }
+}
+
+/// Whether `token` closes a block or is an `else`, which puts its line at
+/// the opener's indentation.
+fn closes(token: &Token) -> bool {
+    token.backslashes != 1 && !token.raw && (token.inner.starts_with('/') || is_else(token.inner))
+}
+
+/// The Sline `#raw` block tags, padded like the other tags.
+fn raw_tags(options: &Options) -> (String, String) {
+    let pad = options.mustache_spacing.padding("#raw");
+    (
+        format!("{{{{{pad}#raw{pad}}}}}"),
+        format!("{{{{{pad}/raw{pad}}}}}"),
+    )
+}
+
+const OPEN_BRACE_ENTITIES: &[&str] = &["&#123;", "&#x7b;", "&#x7B;", "&lbrace;", "&lcub;"];
+const CLOSE_BRACE_ENTITIES: &[&str] = &["&#125;", "&#x7d;", "&#x7D;", "&rbrace;", "&rcub;"];
+
+/// The byte length of the first `count` brace entities in a row at the
+/// start of `text`, or `None` when there are fewer.
+fn entity_run(text: &str, entities: &[&str], count: usize) -> Option<usize> {
+    let mut length = 0;
+    for _ in 0..count {
+        let entity = entities
+            .iter()
+            .find(|entity| text[length..].starts_with(**entity))?;
+        length += entity.len();
+    }
+    Some(length)
+}
+
+/// Rewrites mustaches written with brace entities, such as
+/// `&#123;&#123;name&#125;&#125;`, as literal mustaches inside `raw` block
+/// tags, or returns `None` when `text` has none.
+fn normalize_escaped_braces(text: &str, (open, close): &(String, String)) -> Option<String> {
+    let mut normalized = String::new();
+    let mut copied = 0;
+    let mut search = 0;
+    while let Some(found) = text[search..].find('&') {
+        let start = search + found;
+        search = start + 1;
+        // Three braces open an unescaped mustache, which needs three to close.
+        let Some(braces) = [3, 2]
+            .into_iter()
+            .find(|&count| entity_run(&text[start..], OPEN_BRACE_ENTITIES, count).is_some())
+        else {
+            continue;
+        };
+        let Some(end) = text[start..].match_indices('&').find_map(|(offset, _)| {
+            let at = start + offset;
+            entity_run(&text[at..], CLOSE_BRACE_ENTITIES, braces)
+                .filter(|_| offset > 0)
+                .map(|length| at + length)
+        }) else {
+            break;
+        };
+        let mut mustache = text[start..end].to_string();
+        for entity in OPEN_BRACE_ENTITIES {
+            mustache = mustache.replace(entity, "{");
+        }
+        for entity in CLOSE_BRACE_ENTITIES {
+            mustache = mustache.replace(entity, "}");
+        }
+        normalized.push_str(&text[copied..start]);
+        normalized.push_str(open);
+        normalized.push_str(&mustache);
+        normalized.push_str(close);
+        copied = end;
+        search = end;
+    }
+    if copied == 0 {
+        return None;
+    }
+    normalized.push_str(&text[copied..]);
+    Some(normalized)
+}
+
+/// Whether `token` is a block tag, `else` or comment, the tags Handlebars
+/// treats as standalone when they are alone on their line. Raw and comment
+/// blocks are copied whole and keep their lines.
+fn standalone_kind(token: &Token) -> bool {
+    let inner = token.inner.trim();
+    token.backslashes == 0
+        && !token.raw
+        && inner != "#raw"
+        && !inner.starts_with("#comment")
+        && (inner.starts_with(['#', '/', '^', '!']) || is_else(inner))
+}
+
+/// The helper called by a `{{helper ...}}` tag if its calls are dropped
+/// from the output: a built-in debugging helper, or one mapped to nothing.
+fn dropped_helper<'a>(token_trim: &'a str, options: &Options) -> Option<&'a str> {
+    let helper = split_arguments(token_trim).first().copied()?;
+    let dropped = match options.helpers.get(helper) {
+        Some(mapped) => mapped.is_empty(),
+        None => DROPPED_HELPERS.contains(&helper),
+    };
+    dropped.then_some(helper)
+}
+
+/// When `start..end` is alone on its line, returns the line's start and the
+/// offset just past its line break, so the whole line can be dropped.
+/// Only whitespace copied from `index` onwards counts as part of the line.
+fn standalone_line(input: &str, index: usize, start: usize, end: usize) -> Option<(usize, usize)> {
+    let line_start = match input[..start].rfind('\n') {
+        Some(newline) => newline + 1,
+        // The byte order mark skipped at the start is not part of the line.
+        None if input[..index].chars().eq([BOM]) => index,
+        None => 0,
+    };
+    if line_start < index || !input[line_start..start].trim().is_empty() {
+        return None;
+    }
+    let rest = &input[end..];
+    let line_end = rest
+        .find('\n')
+        .map_or(input.len(), |newline| end + newline + 1);
+    input[end..line_end]
+        .trim()
+        .is_empty()
+        .then_some((line_start, line_end))
+}
+
+#[derive(Clone, Copy)]
+struct BlockClose {
+    start: usize,
+    end: usize,
+    trim_right: bool,
+}
+
+fn find_block_close(source: &str, start_index: usize, name: &str) -> Option<BlockClose> {
+    let close_tag = format!("/{}", name);
+    let mut depth = 0;
+    for token in Tokens::new(source, start_index) {
+        if token.backslashes == 1 {
+            continue;
+        }
+        if let Some(rest) = token.inner.strip_prefix('#')
+            && block_name(rest) == name
+        {
+            depth += 1;
+        } else if token.inner == close_tag {
+            if depth == 0 {
+                return Some(BlockClose {
+                    start: token.start,
+                    end: token.end,
+                    trim_right: token.trim_right,
+                });
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+fn find_raw_block_close(source: &str, start_index: usize, name: &str) -> Option<BlockClose> {
+    let close_tag = format!("/{}", name);
+    let mut index = start_index;
+    while let Some(relative_start) = source[index..].find("{{{{/") {
+        let open = index + relative_start;
+        let search_start = open + 4;
+        let close = search_start + source[search_start..].find("}}}}")?;
+        if source[search_start..close].trim() == close_tag {
+            return Some(BlockClose {
+                start: open,
+                end: close + 4,
+                trim_right: false,
+            });
+        }
+        index = close + 4;
+    }
+    None
+}
+
+/// Remembers the searches for block closes over one input, so a template
+/// full of unclosed openers is not rescanned to its end from each of them.
+struct Closes<'a> {
+    source: &'a str,
+    /// Once a search for a `{{#comment}}` or `{{#raw}}` close has failed, the
+    /// closes of the blocks opened after it, found in one forward pass.
+    blocks: HashMap<&'static str, BlockMatches>,
+    /// For each `{{{{name}}}}`, the offset after which its close is missing.
+    raw_missing_from: HashMap<&'a str, usize>,
+}
+
+/// The closes of the `{{#name}}` blocks opened after `from`, keyed by the
+/// end of their opening tag, with `None` for the ones never closed.
+struct BlockMatches {
+    from: usize,
+    closes: HashMap<usize, Option<BlockClose>>,
+}
+
+impl<'a> Closes<'a> {
+    fn new(source: &'a str) -> Self {
+        Closes {
+            source,
+            blocks: HashMap::new(),
+            raw_missing_from: HashMap::new(),
+        }
+    }
+
+    /// Like [`find_block_close`], for a `{{#name}}` tag ending at `start`.
+    fn block(&mut self, start: usize, name: &'static str) -> Option<BlockClose> {
+        if let Some(matches) = self.blocks.get(name)
+            && start >= matches.from
+            && let Some(&close) = matches.closes.get(&start)
+        {
+            return close;
+        }
+        let close = find_block_close(self.source, start, name);
+        if close.is_none() && !self.blocks.contains_key(name) {
+            self.blocks
+                .insert(name, BlockMatches::new(self.source, start, name));
+        }
+        close
+    }
+
+    /// Like [`find_raw_block_close`], for a `{{{{name}}}}` tag ending at
+    /// `start`. Raw blocks do not nest, so once a close is missing after
+    /// one offset it is missing after every later one too.
+    fn raw(&mut self, start: usize, name: &'a str) -> Option<BlockClose> {
+        if self
+            .raw_missing_from
+            .get(name)
+            .is_some_and(|&missing_from| start >= missing_from)
+        {
+            return None;
+        }
+        let close = find_raw_block_close(self.source, start, name);
+        if close.is_none() {
+            self.raw_missing_from.entry(name).or_insert(start);
+        }
+        close
+    }
+}
+
+impl BlockMatches {
+    /// Pairs the `{{#name}}` and `{{/name}}` tags after `from`, where a
+    /// search for the close of the block opened just before has failed.
+    fn new(source: &str, from: usize, name: &str) -> Self {
+        let close_tag = format!("/{}", name);
+        let mut closes = HashMap::from([(from, None)]);
+        let mut open = Vec::new();
+        for token in Tokens::new(source, from) {
+            if token.backslashes == 1 {
+                continue;
+            }
+            if let Some(rest) = token.inner.strip_prefix('#')
+                && block_name(rest) == name
+            {
+                open.push(token.end);
+            } else if token.inner == close_tag
+                && let Some(opened) = open.pop()
+            {
+                let close = BlockClose {
+                    start: token.start,
+                    end: token.end,
+                    trim_right: token.trim_right,
+                };
+                closes.insert(opened, Some(close));
+            }
+        }
+        closes.extend(open.into_iter().map(|opened| (opened, None)));
+        BlockMatches { from, closes }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    start: usize,
+    end: usize,
+    inner: &'a str,
+    leading: &'a str,
+    trailing: &'a str,
+    trim_left: bool,
+    trim_right: bool,
+    triple: bool,
+    raw: bool,
+    backslashes: usize,
+    /// For a `{{` that is not closed, the offset where that became
+    /// certain: the next `{{`, or the end of the input. `end` is then just
+    /// past the opening braces and `inner` is empty.
+    unterminated: Option<usize>,
+}
+
+struct Tokens<'a> {
+    source: &'a str,
+    index: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(source: &'a str, index: usize) -> Self {
+        Tokens { source, index }
+    }
+
+    fn seek(&mut self, index: usize) {
+        self.index = index;
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    // Every offset here comes from searching for ASCII delimiters, so slicing
+    // on them always lands on a char boundary even next to multibyte text.
+    fn next(&mut self) -> Option<Token<'a>> {
+        let source = self.source;
+        let start = self.index + source[self.index..].find("{{")?;
+        let raw = source[start..].starts_with("{{{{");
+        let triple = !raw && source[start..].starts_with("{{{");
+        let (open_len, close_seq) = if raw {
+            (4, "}}}}")
+        } else if triple {
+            (3, "}}}")
+        } else {
+            (2, "}}")
+        };
+
+        let search_start = start + open_len;
+        let comment = source[search_start..]
+            .trim_start_matches(|character: char| character.is_whitespace() || character == '~');
+        let is_comment = comment.starts_with('!');
+        let plain_close = || {
+            source[search_start..]
+                .find(close_seq)
+                .map(|relative| search_start + relative)
+        };
+        let (close, reopen) = if raw {
+            (plain_close(), None)
+        } else if !triple && comment.starts_with("!--") {
+            // A long comment may hold `}}` and only ends at `--}}`.
+            (
+                long_comment_close(source, search_start).or_else(plain_close),
+                None,
+            )
+        } else if is_comment {
+            (plain_close(), None)
+        } else {
+            match find_tag_close(source, search_start, close_seq) {
+                Some(close) => (Some(close), find_tag_close(source, search_start, "{{")),
+                // With an unterminated quote the next `{{` may be quoted,
+                // so only a tag with no `}}` at all counts as unclosed.
+                None => (plain_close(), None),
+            }
+        };
+        let close = match close {
+            Some(close) if reopen.is_none_or(|reopen| reopen > close) => close,
+            _ => {
+                self.index = search_start;
+                return Some(Token {
+                    start,
+                    end: search_start,
+                    inner: "",
+                    leading: "",
+                    trailing: "",
+                    trim_left: false,
+                    trim_right: false,
+                    triple,
+                    raw,
+                    backslashes: backslashes(source, start),
+                    unterminated: Some(reopen.unwrap_or(source.len())),
+                });
+            }
+        };
+        self.index = close + close_seq.len();
+
+        let content = &source[search_start..close];
+        let (content, trim_left) = match content.trim_start().strip_prefix('~') {
+            Some(rest) => (rest, true),
+            None => (content, false),
+        };
+        let (content, trim_right) = match content.trim_end().strip_suffix('~') {
+            Some(rest) => (rest, true),
+            None => (content, false),
+        };
+        let inner = content.trim();
+
+        Some(Token {
+            start,
+            end: self.index,
+            inner,
+            leading: &content[..content.len() - content.trim_start().len()],
+            trailing: &content[content.trim_end().len()..],
+            trim_left,
+            trim_right,
+            triple,
+            raw,
+            backslashes: backslashes(source, start),
+            unterminated: None,
+        })
+    }
+}
+
+/// The backslashes, up to two, escaping the tag at `start`.
+fn backslashes(source: &str, start: usize) -> usize {
+    source[..start]
+        .bytes()
+        .rev()
+        .take(2)
+        .take_while(|&byte| byte == b'\\')
+        .count()
+}
+
+/// The offset of the `}}` ending a `{{!-- --}}` comment whose body starts
+/// at or before `start_index`, allowing a `~` after the dashes.
+fn long_comment_close(source: &str, start_index: usize) -> Option<usize> {
+    let body = start_index + source[start_index..].find("!--")? + 3;
+    let mut index = body;
+    while let Some(relative) = source[index..].find("--") {
+        let dashes = index + relative + 2;
+        let rest = &source[dashes..];
+        let rest = rest.strip_prefix('~').unwrap_or(rest);
+        if rest.starts_with("}}") {
+            return Some(source.len() - rest.len());
+        }
+        index = dashes - 1;
+    }
+    None
+}
+
+fn find_tag_close(source: &str, start_index: usize, close_seq: &str) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut quote = None;
+    for index in start_index..bytes.len() {
+        let byte = bytes[index];
+        match quote {
+            Some(open) if byte == open => quote = None,
+            Some(_) => {}
+            None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+            None if bytes[index..].starts_with(close_seq.as_bytes()) => return Some(index),
+            None => {}
+        }
+    }
+    None
+}
+
+struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    cursor: Cell<(usize, usize)>,
+    /// Position of `source` in a larger input, added to every span.
+    offset: usize,
+    line_offset: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(source: &'a str) -> Self {
+        LineIndex::at(source, 0, 0)
+    }
+
+    /// Indexes `source` as the part of a larger input that starts at byte
+    /// `offset`, after `line_offset` lines.
+    fn at(source: &'a str, offset: usize, line_offset: usize) -> Self {
+        // A byte order mark is not part of the first line's columns.
+        let first = if offset == 0 && source.starts_with(BOM) {
+            BOM.len_utf8()
+        } else {
+            0
+        };
+        let line_starts = std::iter::once(first)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        LineIndex {
+            source,
+            line_starts,
+            cursor: Cell::new((0, 1)),
+            offset,
+            line_offset,
+        }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.line_starts.partition_point(|&offset| offset <= start);
+        let line_start = self.line_starts[line - 1];
+
+        // Tags are located in increasing order, so counting on from the
+        // previous position keeps column lookups linear on very long lines.
+        let (cursor_offset, cursor_column) = self.cursor.get();
+        let (from, column) = if (line_start..=start).contains(&cursor_offset) {
+            (cursor_offset, cursor_column)
+        } else {
+            (line_start, 1)
+        };
+        let column = column + self.source[from..start].chars().count();
+        self.cursor.set((start, column));
+
+        Span {
+            start: start + self.offset,
+            end: end + self.offset,
+            line: line + self.line_offset,
+            column,
+        }
+    }
+}
+
+/// Validates block structure without building any output. A closing tag
+/// that looks like a typo of the one expected comes with a suggestion.
+///
+/// ```
+/// use sline_transpiler::{Code, check_syntax};
+///
+/// let diagnostics = check_syntax("{{#each items}}{{name}}{{/eech}}");
+/// assert_eq!(diagnostics[0].code, Code::MismatchedClose);
+/// assert!(diagnostics[0].message.ends_with("did you mean {{/each}}?"));
+///
+/// let diagnostics = check_syntax("done{{/if}}");
+/// assert_eq!(diagnostics[0].code, Code::UnexpectedClose);
+/// assert_eq!(diagnostics[0].message, "Unexpected closing tag /if");
+/// ```
+pub fn check_syntax(input: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut stack: Vec<(&str, Span)> = Vec::new();
+    let lines = LineIndex::new(input);
+    let mut tokens = Tokens::new(input, 0);
+    let mut block_closes = Closes::new(input);
+
+    while let Some(token) = tokens.next() {
+        if token.backslashes == 1 {
+            continue;
+        }
+        if token.unterminated.is_some() {
+            diagnostics.push(unterminated_tag(&token, &lines));
+            continue;
+        }
+
+        let token_trim = token.inner.trim();
+        let span = lines.span(token.start, token.end);
+
+        if token_trim.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                Code::EmptyTag,
+                "Empty mustache tag".to_string(),
+                span,
+            ));
+            continue;
+        }
+
+        if token_trim.starts_with('!') {
+            continue;
+        }
+
+        if token.raw {
+            match block_closes.raw(token.end, token_trim) {
+                Some(close_end) => tokens.seek(close_end.end),
+                None => diagnostics.push(Diagnostic::new(
+                    Code::UnclosedBlock,
+                    format!("Unclosed {{{{{{{{{token_trim}}}}}}}}} raw block"),
+                    span,
+                )),
+            }
+            continue;
+        }
+
+        if token_trim == "#raw"
+            && let Some(close_end) = block_closes.block(token.end, "raw")
+        {
+            tokens.seek(close_end.end);
+            continue;
+        }
+
+        if token_trim.starts_with("#comment") {
+            match block_closes.block(token.end, "comment") {
+                Some(close_end) => tokens.seek(close_end.end),
+                None => diagnostics.push(Diagnostic::new(
+                    Code::UnclosedBlock,
+                    "Unclosed {{#comment}} block".to_string(),
+                    span,
+                )),
+            }
+            continue;
+        }
+
+        if let Some(rest) = token_trim.strip_prefix('#') {
+            if matches!(rest, "if" | "unless") {
+                diagnostics.push(missing_condition(token_trim, span));
+            }
+            stack.push((block_name(rest), span));
+            continue;
+        }
+
+        if let Some(rest) = inverted_section(token_trim) {
+            stack.push((block_name(rest), span));
+            continue;
+        }
+
+        if is_else(token_trim) && stack.is_empty() {
+            diagnostics.push(stray_else(token_trim, span));
+            continue;
+        }
+
+        if let Some(rest) = token_trim.strip_prefix('/') {
+            let name = block_name(rest);
+            match stack.pop() {
+                Some((open, _)) if open == name => {}
+                Some((open, open_span)) => diagnostics.push(Diagnostic::new(Code::MismatchedClose, format!(
+                        "Mismatched closing tag: expected /{open}, found /{name} ({open} opened at {}:{}){}",
+                        open_span.line, open_span.column, did_you_mean(name, open)
+                    ), span)),
+                None => diagnostics.push(Diagnostic::new(Code::UnexpectedClose, format!("Unexpected closing tag /{name}"), span)),
+            }
+        }
+    }
+
+    for (name, span) in stack {
+        diagnostics.push(Diagnostic::new(
+            Code::UnclosedBlock,
+            format!("Unclosed block: {name} (opened at offset {})", span.start),
+            span,
+        ));
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// What follows `keyword` when `tag` opens that block, such as ` items`
+/// for `#each items`. A section whose name only starts with the keyword,
+/// such as `#eachItem`, does not match.
+fn block_arguments<'a>(tag: &'a str, keyword: &str) -> Option<&'a str> {
+    tag.strip_prefix(keyword).filter(|rest| {
+        rest.is_empty()
+            || rest.starts_with(|character: char| character.is_whitespace() || character == '~')
+    })
+}
+
+fn block_name(rest: &str) -> &str {
+    // Decorator blocks such as `{{#*inline}}` close with the bare name.
+    rest.trim_start_matches('*')
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+}
+
+fn transform_tag(
+    tag: &str,
+    span: Span,
+    stack: &mut Vec<BlockContext>,
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if let Some(rest) = block_arguments(tag, "#each") {
+        let (expr, params, _) = split_block_params(rest);
+        let unclosed = block_params_start(rest)
+            .is_some_and(|pos| !rest[pos + BLOCK_PARAMS.len()..].contains('|'));
+        if expr.is_empty() || unclosed {
+            let message = if unclosed {
+                "#each block params are missing their closing |"
+            } else {
+                "#each requires an iterable"
+            };
+            diagnostics.push(Diagnostic::new(
+                Code::MalformedEach,
+                message.to_string(),
+                span,
+            ));
+            stack.push(BlockContext::Section {
+                name: "each".to_string(),
+                converted: false,
+                open: span,
+            });
+            return tag.to_string();
+        }
+        if let [_, _, extra @ ..] = params.as_slice()
+            && !extra.is_empty()
+        {
+            diagnostics.push(Diagnostic::new(
+                Code::MalformedEach,
+                format!(
+                    "#each binds at most an item and an index; dropped the block params {}",
+                    extra.join(" ")
+                ),
+                span,
+            ));
+        }
+        let default_alias =
+            unbound_alias(options.default_alias.as_deref().unwrap_or("item"), stack);
+        let (expr, alias, mut index, mut hash) = parse_each(rest.trim(), &default_alias);
+        if let Some(position) = hash
+            .iter()
+            .position(|argument| split_hash(argument).is_some_and(|(key, _)| key == "key"))
+        {
+            let argument = hash.remove(position);
+            let key = loop_key(&argument["key=".len()..], &alias);
+            diagnostics.push(Diagnostic::new(
+                Code::DroppedHashArguments,
+                format!("#each {argument} has no Sline equivalent and was dropped; key the items by {key} where they are rendered"),
+                span,
+            ));
+        }
+        if !hash.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                Code::DroppedHashArguments,
+                format!(
+                    "#each hash arguments have no Sline equivalent and were dropped: {}",
+                    hash.join(" ")
+                ),
+                span,
+            ));
+        }
+        let expr = match transform_range(&expr, span, stack, options, diagnostics) {
+            Some(range) => range,
+            None => transform_condition(&expr, span, stack, options, diagnostics),
+        };
+        let object = options.each_as_object && index.is_none();
+        if object {
+            index = Some(unbound_alias("key", stack));
+        } else if index.is_none()
+            && let Some(ref name) = options.always_index
+        {
+            let free = |candidate: &String| {
+                *candidate != alias && !stack.iter().any(|context| binds(context, candidate))
+            };
+            let bound = std::iter::once(name.clone())
+                .chain((2..).map(|number| format!("{name}{number}")))
+                .find(free)
+                .unwrap_or_default();
+            if bound != *name {
+                diagnostics.push(Diagnostic::new(
+                    Code::AliasShadowed,
+                    format!("Loop index {name} is already bound; named it {bound} instead"),
+                    span,
+                ));
+            }
+            index = Some(bound);
+        }
+        let header = match index {
+            Some(ref index) => format!("#for {}, {} in {}", alias, index, expr),
+            None => format!("#for {} in {}", alias, expr),
+        };
+        // Generated aliases (`item`, `key`) are not named by the template,
+        // so only explicit block params are checked.
+        let explicit = !split_block_params(rest).1.is_empty();
+        for name in std::iter::once(&alias)
+            .chain(index.as_ref())
+            .filter(|_| explicit)
+        {
+            if let Some(outer) = stack.iter().find(|context| binds(context, name)) {
+                let open = outer.open();
+                diagnostics.push(Diagnostic::new(Code::AliasShadowed, format!(
+                        "Loop alias {name} shadows the one bound at {}:{}; references to it may be ambiguous",
+                        open.line, open.column
+                    ), span));
+            }
+        }
+        stack.push(BlockContext::Each {
+            alias,
+            index,
+            object,
+            empty: false,
+            key_used: Cell::new(false),
+            index_used: Cell::new(false),
+            open: span,
+        });
+        return header;
+    }
+
+    if tag == "/each" {
+        return close_block("each", span, stack, diagnostics).unwrap_or_else(|| "/for".to_string());
+    }
+
+    if let Some(rest) = block_arguments(tag, "#unless") {
+        if rest.trim().is_empty() {
+            diagnostics.push(missing_condition("#unless", span));
+            stack.push(BlockContext::Unless { open: span });
+            return tag.to_string();
+        }
+        let condition = transform_condition(rest.trim(), span, stack, options, diagnostics);
+        stack.push(BlockContext::Unless { open: span });
+        return format!("#if {}", negate(&condition));
+    }
+
+    if tag == "/unless" {
+        return close_block("unless", span, stack, diagnostics)
+            .unwrap_or_else(|| "/if".to_string());
+    }
+
+    if let Some(rest) = block_arguments(tag, "#if") {
+        if rest.trim().is_empty() {
+            diagnostics.push(missing_condition("#if", span));
+            stack.push(BlockContext::If { open: span });
+            return tag.to_string();
+        }
+        let condition = transform_condition(rest.trim(), span, stack, options, diagnostics);
+        stack.push(BlockContext::If { open: span });
+        return format!("#if {}", condition);
+    }
+
+    if tag == "/if" {
+        return close_block("if", span, stack, diagnostics).unwrap_or_else(|| "/if".to_string());
+    }
+
+    if is_else(tag) && stack.is_empty() {
+        diagnostics.push(stray_else(tag, span));
+        return tag.to_string();
+    }
+
+    if is_else(tag)
+        && let Some(BlockContext::Unless { open }) = stack.last()
+    {
+        diagnostics.push(Diagnostic::new(
+            Code::UnlessElse,
+            format!(
+                "{{{{{tag}}}}} of the #unless at {}:{} runs when its condition is true, since the #unless became a negated #if",
+                open.line, open.column
+            ),
+            span,
+        ));
+    }
+
+    if tag == "else" || tag == "^" {
+        if let Some(BlockContext::Each { empty, .. }) = stack.last_mut() {
+            *empty = true;
+        }
+        return "else".to_string();
+    }
+
+    if matches!(stack.last(), Some(BlockContext::Each { .. })) && tag.starts_with("else ") {
+        diagnostics.push(Diagnostic::new(
+            Code::ChainedElseInEach,
+            "Chained else inside #each has no Sline equivalent; left as-is".to_string(),
+            span,
+        ));
+        return tag.to_string();
+    }
+
+    if let Some(rest) = tag.strip_prefix("else unless ") {
+        let condition = transform_condition(rest.trim(), span, stack, options, diagnostics);
+        return format!("else if {}", negate(&condition));
+    }
+
+    if let Some(rest) = tag.strip_prefix("else if ") {
+        let condition = transform_condition(rest.trim(), span, stack, options, diagnostics);
+        return format!("else if {}", condition);
+    }
+
+    if let Some(rest) = tag.strip_prefix('>') {
+        return transform_partial(tag, rest.trim(), span, stack, options, diagnostics);
+    }
+
+    if let Some(rest) = block_arguments(tag, "#with") {
+        let (expr, params, _) = split_block_params(rest);
+        if expr.is_empty() || expr.starts_with('(') {
+            diagnostics.push(Diagnostic::new(
+                Code::UnconvertedWith,
+                "Handlebars #with blocks are not converted".to_string(),
+                span,
+            ));
+            stack.push(BlockContext::With {
+                expr: None,
+                alias: None,
+                open: span,
+            });
+            return tag.to_string();
+        }
+
+        let expr = transform_expression(expr, span, stack, options, diagnostics);
+        let header = format!("#if {}", expr);
+        stack.push(BlockContext::With {
+            expr: Some(expr),
+            alias: params.first().map(|alias| alias.to_string()),
+            open: span,
+        });
+        return header;
+    }
+
+    if tag == "/with" {
+        return close_block("with", span, stack, diagnostics).unwrap_or_else(|| tag.to_string());
+    }
+
+    if let Some(rest) = tag.strip_prefix("#*inline") {
+        let name = rest.trim().trim_matches(['"', '\'']);
+        diagnostics.push(Diagnostic::new(
+            Code::InlinePartial,
+            format!(
+                "Inline partial \"{name}\" has no Sline equivalent; move it to its own partial file"
+            ),
+            span,
+        ));
+        stack.push(BlockContext::Inline { open: span });
+        return tag.to_string();
+    }
+
+    if tag == "/inline" {
+        return close_block("inline", span, stack, diagnostics).unwrap_or_else(|| tag.to_string());
+    }
+
+    // Decorators run when the template is compiled and Sline has nothing
+    // like them, so their arguments are not converted either.
+    if let Some(rest) = tag.strip_prefix('*') {
+        let name = rest.split_whitespace().next().unwrap_or_default();
+        diagnostics.push(Diagnostic::new(
+            Code::Decorator,
+            format!("Decorator {{{{*{name}}}}} has no Sline equivalent; left as-is"),
+            span,
+        ));
+        return tag.to_string();
+    }
+
+    if let Some(rest) = tag.strip_prefix('#') {
+        let name = block_name(rest);
+        let passthrough = || {
+            options
+                .passthrough_blocks
+                .iter()
+                .find(|block| block.as_str() == name)
+        };
+        if let Some(mapped) = options.helpers.get(name).or_else(passthrough) {
+            let header = format!("#{}{}", mapped, &rest[name.len()..]);
+            stack.push(BlockContext::Helper {
+                name: name.to_string(),
+                mapped: mapped.clone(),
+                open: span,
+            });
+            return header;
+        }
+    }
+
+    if let Some(rest) = tag.strip_prefix('/')
+        && let Some(BlockContext::Helper { name, mapped, .. }) = stack.last()
+        && name == rest.trim()
+    {
+        let close = format!("/{}", mapped);
+        stack.pop();
+        return close;
+    }
+
+    // An inverted section renders when its value is falsy, like `#unless`.
+    if let Some(rest) = inverted_section(tag) {
+        let name = block_name(rest);
+        let condition = transform_condition(rest.trim(), span, stack, options, diagnostics);
+        diagnostics.push(Diagnostic::new(Code::SectionAsIf, format!(
+                "Inverted section {{{{^{name}}}}} converted to a negated #if; check {name}.size instead if {name} is a list"
+            ), span));
+        stack.push(BlockContext::Section {
+            name: name.to_string(),
+            converted: true,
+            open: span,
+        });
+        return format!("#if {}", negate(&condition));
+    }
+
+    if let Some(rest) = tag.strip_prefix('#')
+        && !rest.starts_with(['>', '*'])
+    {
+        let name = block_name(rest);
+        let converted = rest.trim() == name && is_simple_path(name);
+        let header =
+            if converted {
+                let condition = transform_argument(name, span, stack, options, diagnostics);
+                diagnostics.push(Diagnostic::new(Code::SectionAsIf, format!(
+                    "Section {{{{#{name}}}}} converted to #if; use #for instead if {name} is a list"
+                ), span));
+                format!("#if {}", condition)
+            } else {
+                transform_expression(tag, span, stack, options, diagnostics)
+            };
+        stack.push(BlockContext::Section {
+            name: name.to_string(),
+            converted,
+            open: span,
+        });
+        return header;
+    }
+
+    if let Some(rest) = tag.strip_prefix('/') {
+        let name = block_name(rest);
+        return close_block(name, span, stack, diagnostics).unwrap_or_else(|| tag.to_string());
+    }
+
+    transform_expression(tag, span, stack, options, diagnostics)
+}
+
+/// `name`, or `name` numbered from 2 when an enclosing block already binds
+/// it, so nested loops without block params keep the outer alias usable.
+fn unbound_alias(name: &str, stack: &[BlockContext]) -> String {
+    let bound = |candidate: &str| stack.iter().any(|context| binds(context, candidate));
+    if !bound(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|number| format!("{name}{number}"))
+        .find(|candidate| !bound(candidate))
+        .unwrap_or_default()
+}
+
+fn binds(context: &BlockContext, name: &str) -> bool {
+    match context {
+        BlockContext::Each { alias, index, .. } => alias == name || index.as_deref() == Some(name),
+        BlockContext::With {
+            alias: Some(alias), ..
+        } => alias == name,
+        _ => false,
+    }
+}
+
+/// `tag` with its block keyword lowercased, such as `#each items` for
+/// `#EACH items` or `else if x` for `Else If x`, when it is not already.
+fn lowercase_keyword(tag: &str) -> Option<String> {
+    fn split(text: &str) -> (String, &str) {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        (text[..end].to_ascii_lowercase(), &text[end..])
+    }
+    let rest = tag.trim_start_matches(['#', '/']);
+    let sigil = &tag[..tag.len() - rest.len()];
+    let (keyword, tail) = split(rest);
+    let normalized = match keyword.as_str() {
+        "each" | "if" | "unless" | "with" if sigil.len() == 1 => format!("{sigil}{keyword}{tail}"),
+        "else" if sigil.is_empty() => {
+            let spaced = tail.trim_start();
+            match split(spaced) {
+                (chained, after) if matches!(chained.as_str(), "if" | "unless") => {
+                    let space = &tail[..tail.len() - spaced.len()];
+                    format!("else{space}{chained}{after}")
+                }
+                _ => format!("else{tail}"),
+            }
+        }
+        _ => return None,
+    };
+    (normalized != tag).then_some(normalized)
+}
+
+/// Whether `tag` is an `{{else}}`, including the `{{^}}` spelling.
+fn is_else(tag: &str) -> bool {
+    tag == "else" || tag == "^" || tag.starts_with("else ")
+}
+
+/// The section opened by an inverted `{{^name}}` tag, if `tag` is one.
+fn inverted_section(tag: &str) -> Option<&str> {
+    tag.strip_prefix('^').filter(|rest| !rest.trim().is_empty())
+}
+
+fn unterminated_tag(token: &Token, lines: &LineIndex) -> Diagnostic {
+    let span = lines.span(token.start, token.end);
+    Diagnostic::new(
+        Code::UnterminatedTag,
+        format!("Unterminated mustache at offset {}", span.start),
+        span,
+    )
+}
+
+fn missing_condition(keyword: &str, span: Span) -> Diagnostic {
+    Diagnostic::new(
+        Code::MissingCondition,
+        format!("{keyword} requires a condition"),
+        span,
+    )
+}
+
+fn stray_else(tag: &str, span: Span) -> Diagnostic {
+    Diagnostic::new(
+        Code::StrayElse,
+        format!("{{{{{tag}}}}} outside of an #if, #unless or #each block"),
+        span,
+    )
+}
+
+/// Pops the innermost block for a `/name` tag and returns the Sline tag that
+/// closes it. A mismatched close still closes the innermost block, so the
+/// output stays balanced; `None` when no block is open.
+fn close_block(
+    name: &str,
+    span: Span,
+    stack: &mut Vec<BlockContext>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<String> {
+    let context = stack.pop();
+    match context {
+        Some(ref context) if context.name() == name => {}
+        Some(ref context) => diagnostics.push(Diagnostic::new(
+            Code::MismatchedClose,
+            format!(
+                "Mismatched closing tag: expected /{}, found /{name} ({} opened at {}:{}){}",
+                context.name(),
+                context.name(),
+                context.open().line,
+                context.open().column,
+                did_you_mean(name, context.name())
+            ),
+            span,
+        )),
+        None => diagnostics.push(Diagnostic::new(
+            Code::UnexpectedClose,
+            format!("Unexpected closing tag /{name}"),
+            span,
+        )),
+    }
+    context.as_ref().map(close_tag)
+}
+
+/// A suggestion to append when the closing tag `/found` looks like a typo
+/// of `/expected`, or nothing when the names are too far apart.
+fn did_you_mean(found: &str, expected: &str) -> String {
+    let limit = if expected.chars().count() <= 3 { 1 } else { 2 };
+    if edit_distance(found, expected) <= limit {
+        format!("; did you mean {{{{/{expected}}}}}?")
+    } else {
+        String::new()
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// The Sline tag that closes the output of `context`'s opening tag.
+fn close_tag(context: &BlockContext) -> String {
+    match context {
+        BlockContext::Each { .. } => "/for".to_string(),
+        BlockContext::If { .. } | BlockContext::Unless { .. } => "/if".to_string(),
+        BlockContext::With { expr: Some(_), .. } => "/if".to_string(),
+        BlockContext::With { expr: None, .. } => "/with".to_string(),
+        BlockContext::Helper { mapped, .. } => format!("/{mapped}"),
+        BlockContext::Inline { .. } => "/inline".to_string(),
+        BlockContext::Section {
+            converted: true, ..
+        } => "/if".to_string(),
+        BlockContext::Section { name, .. } => format!("/{name}"),
+    }
+}
+
+/// Converts an `#if` or `#unless` condition or an `#each` iterable. Helper
+/// calls are converted like `{{helper ...}}` tags, and operator expressions
+/// such as `a && b` or `items.size > 0` have each operand converted in place.
+/// A condition that is a single comparison such as `(eq a b)` becomes
+/// `a == b`.
+fn transform_condition(
+    condition: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let arguments = split_arguments(condition);
+    if !arguments.iter().any(|argument| is_operator(argument)) {
+        return match arguments.as_slice() {
+            [] => String::new(),
+            [_] => match transform_comparison(condition, span, stack, options, diagnostics) {
+                Some(comparison) => comparison,
+                None => transform_argument(condition, span, stack, options, diagnostics),
+            },
+            [helper, ..] => {
+                transform_helper_call(condition, helper, span, stack, options, diagnostics)
+            }
+        };
+    }
+
+    let mut converted = String::new();
+    let mut copied = 0;
+    for argument in arguments {
+        let start = argument.as_ptr() as usize - condition.as_ptr() as usize;
+        converted.push_str(&condition[copied..start]);
+        copied = start + argument.len();
+        converted.push_str(&transform_operand(
+            argument,
+            span,
+            stack,
+            options,
+            diagnostics,
+        ));
+    }
+    converted.push_str(&condition[copied..]);
+    converted
+}
+
+/// Converts an `(range start end)` iterable into `start..end`, or returns
+/// `None` for any other iterable.
+fn transform_range(
+    iterable: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<String> {
+    let inner = iterable.strip_prefix('(')?.strip_suffix(')')?;
+    let [helper, start, end] = split_arguments(inner)[..] else {
+        return None;
+    };
+    let name = options.range_helper.as_deref().unwrap_or("range");
+    if helper != name
+        || options.helpers.contains_key(helper)
+        || split_hash(start).is_some()
+        || split_hash(end).is_some()
+    {
+        return None;
+    }
+    let start = transform_argument(start, span, stack, options, diagnostics);
+    let end = transform_argument(end, span, stack, options, diagnostics);
+    Some(format!("{start}..{end}"))
+}
+
+/// Converts a condition that is a single `(eq a b)` or other operator
+/// helper call into its operator expression, or returns `None` for any
+/// other condition.
+fn transform_comparison(
+    condition: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<String> {
+    let inner = condition.strip_prefix('(')?.strip_suffix(')')?;
+    transform_operator_helper(inner, span, stack, options, diagnostics)
+}
+
+/// Converts a call to one of the [`COMPARISON_HELPERS`] or
+/// [`LOGICAL_HELPERS`], or to `not`, such as `eq a b`, into an operator
+/// expression with its operands converted. Returns `None` for other
+/// helpers, the wrong number of arguments, hash arguments, or when the
+/// helper is mapped in `helpers`.
+fn transform_operator_helper(
+    call: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<String> {
+    let arguments = split_arguments(call);
+    let (&helper, arguments) = arguments.split_first()?;
+    if options.helpers.contains_key(helper)
+        || arguments
+            .iter()
+            .any(|argument| split_hash(argument).is_some())
+    {
+        return None;
+    }
+
+    let find = |helpers: &[(&str, &'static str)]| {
+        helpers
+            .iter()
+            .find(|(name, _)| *name == helper)
+            .map(|&(_, operator)| operator)
+    };
+    let operator = match (find(COMPARISON_HELPERS), find(LOGICAL_HELPERS)) {
+        (Some(operator), _) if arguments.len() == 2 => operator,
+        (_, Some(operator)) if arguments.len() >= 2 => operator,
+        _ if helper == "not" && arguments.len() == 1 => {
+            let operand = transform_argument(arguments[0], span, stack, options, diagnostics);
+            return Some(
+                if operand.contains(char::is_whitespace) && !is_parenthesized(&operand) {
+                    format!("!({operand})")
+                } else {
+                    format!("!{operand}")
+                },
+            );
+        }
+        _ => return None,
+    };
+    let operands: Vec<String> = arguments
+        .iter()
+        .map(|argument| transform_argument(argument, span, stack, options, diagnostics))
+        .collect();
+    Some(operands.join(&format!(" {operator} ")))
+}
+
+fn transform_operand(
+    operand: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if is_operator(operand) {
+        return operand.to_string();
+    }
+    if let Some(rest) = operand.strip_prefix('!') {
+        let rest = transform_operand(rest, span, stack, options, diagnostics);
+        return format!("!{rest}");
+    }
+    // A parenthesized group of operators is a nested condition, not a
+    // helper subexpression.
+    if is_parenthesized(operand) {
+        let inner = &operand[1..operand.len() - 1];
+        if split_arguments(inner)
+            .iter()
+            .any(|argument| is_operator(argument))
+        {
+            let inner = transform_condition(inner.trim(), span, stack, options, diagnostics);
+            return format!("({inner})");
+        }
+    }
+    transform_argument(operand, span, stack, options, diagnostics)
+}
+
+fn is_operator(argument: &str) -> bool {
+    !argument.is_empty()
+        && argument
+            .chars()
+            .all(|character| matches!(character, '!' | '=' | '<' | '>' | '&' | '|'))
+}
+
+/// Whether `condition` is wrapped in a single pair of parentheses, as in
+/// `(a || b)` but not `(a) || (b)`.
+fn is_parenthesized(condition: &str) -> bool {
+    if !condition.starts_with('(') || !condition.ends_with(')') {
+        return false;
+    }
+    let mut depth = 0usize;
+    let mut quote = None;
+    for (offset, character) in condition.char_indices() {
+        match quote {
+            Some(open) if character == open => quote = None,
+            Some(_) => {}
+            None => match character {
+                '"' | '\'' => quote = Some(character),
+                '(' => depth += 1,
+                ')' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return offset == condition.len() - 1;
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    false
+}
+
+/// Negates a converted condition, adding parentheses only when it does not
+/// already have them.
+fn negate(condition: &str) -> String {
+    if is_parenthesized(condition) {
+        format!("!{condition}")
+    } else {
+        format!("!({condition})")
+    }
+}
+
+fn transform_partial(
+    tag: &str,
+    rest: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let arguments = split_arguments(rest);
+    let Some((name, parameters)) = arguments.split_first() else {
+        diagnostics.push(Diagnostic::new(
+            Code::MissingPartialName,
+            "Partial invocation requires a partial name".to_string(),
+            span,
+        ));
+        return tag.to_string();
+    };
+
+    if name.starts_with('(') {
+        diagnostics.push(Diagnostic::new(
+            Code::DynamicPartial,
+            "Dynamic partials are not converted".to_string(),
+            span,
+        ));
+        return tag.to_string();
+    }
+
+    // The first argument that is not `key=value` is the partial's context,
+    // which comes before the hash in the include.
+    let hash: Vec<(&str, &str)> = parameters
+        .iter()
+        .filter_map(|parameter| split_hash(parameter))
+        .collect();
+    let contexts: Vec<&str> = parameters
+        .iter()
+        .copied()
+        .filter(|parameter| split_hash(parameter).is_none())
+        .collect();
+    let mut converted = format!("include {}", quote_partial_name(name));
+    if let Some((context, extra)) = contexts.split_first() {
+        converted.push(' ');
+        converted.push_str(&transform_argument(
+            context,
+            span,
+            stack,
+            options,
+            diagnostics,
+        ));
+        if !extra.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                Code::ExtraPartialContext,
+                format!(
+                    "Partials take a single context argument; dropped {}",
+                    extra.join(" ")
+                ),
+                span,
+            ));
+        }
+    } else if innermost_scope(stack).is_some() {
+        // Handlebars hands a partial the current context, which inside a
+        // loop is the item, so the include names it explicitly.
+        let context = transform_argument("this", span, stack, options, diagnostics);
+        if context != "this" {
+            converted.push(' ');
+            converted.push_str(&context);
+        }
+    }
+    for (key, value) in hash {
+        converted.push(' ');
+        converted.push_str(key);
+        converted.push('=');
+        converted.push_str(&transform_argument(
+            value,
+            span,
+            stack,
+            options,
+            diagnostics,
+        ));
+    }
+    converted
+}
+
+/// Quotes a partial name for `include`. Names are opaque paths such as
+/// `components/button` or `nav.header`; Handlebars also accepts them quoted
+/// or as a `[...]` literal, which are unwrapped first.
+fn quote_partial_name(name: &str) -> String {
+    let unwrapped = ["\"\"", "''", "[]"].iter().find_map(|pair| {
+        let (open, close) = pair.split_at(1);
+        name.strip_prefix(open)?.strip_suffix(close)
+    });
+    let name = unwrapped.unwrap_or(name);
+    if name.contains('"') && !name.contains('\'') {
+        format!("'{name}'")
+    } else {
+        format!("\"{name}\"")
+    }
+}
+
+fn transform_argument(
+    argument: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if is_literal(argument) {
+        return argument.to_string();
+    }
+    transform_expression(argument, span, stack, options, diagnostics)
+}
+
+fn is_literal(argument: &str) -> bool {
+    argument.starts_with(['"', '\''])
+        || is_number(argument)
+        || matches!(argument, "true" | "false" | "null" | "undefined")
+}
+
+/// A number as templates write one: digits with an optional `-` and
+/// fraction. Unlike `f64` parsing, this keeps names such as `inf` and `nan`
+/// paths.
+fn is_number(argument: &str) -> bool {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    let unsigned = argument.strip_prefix('-').unwrap_or(argument);
+    match unsigned.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(unsigned),
+    }
+}
+
+fn split_hash(argument: &str) -> Option<(&str, &str)> {
+    let (key, value) = argument.split_once('=')?;
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|character| character.is_alphanumeric() || matches!(character, '_' | '-'));
+    is_key.then_some((key, value))
+}
+
+fn split_arguments(source: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = None;
+
+    for (offset, character) in source.char_indices() {
+        match quote {
+            Some(open) if character == open => quote = None,
+            Some(_) => {}
+            None => match character {
+                '"' | '\'' => quote = Some(character),
+                '[' => quote = Some(']'),
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                _ if character.is_whitespace() && depth == 0 => {
+                    if let Some(begin) = start.take() {
+                        arguments.push(&source[begin..offset]);
+                    }
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        start.get_or_insert(offset);
+    }
+
+    if let Some(begin) = start {
+        arguments.push(&source[begin..]);
+    }
+    arguments
+}
+
+fn innermost_each(stack: &[BlockContext]) -> Option<(&str, Option<&str>)> {
+    stack.iter().rev().find_map(|context| match context {
+        BlockContext::Each {
+            alias,
+            index,
+            empty: false,
+            ..
+        } => Some((alias.as_str(), index.as_deref())),
+        _ => None,
+    })
+}
+
+fn innermost_scope(stack: &[BlockContext]) -> Option<&BlockContext> {
+    stack.iter().rev().find(|context| context.is_scope())
+}
+
+fn resolve_with(content: String, expr: &str, alias: Option<&str>) -> String {
+    if content == "this" || Some(content.as_str()) == alias {
+        return expr.to_string();
+    }
+    if let Some(rest) = content
+        .strip_prefix("this.")
+        .or_else(|| content.strip_prefix("./"))
+        .or_else(|| alias.and_then(|alias| content.strip_prefix(alias)?.strip_prefix('.')))
+    {
+        return format!("{}.{}", expr, rest);
+    }
+    if is_simple_path(&content) {
+        return format!("{}.{}", expr, content);
+    }
+    content
+}
+
+fn is_simple_path(content: &str) -> bool {
+    content
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || matches!(first, '_' | '['))
+        && split_path(content).iter().all(|segment| {
+            segment.starts_with('[') && segment.ends_with(']')
+                || segment
+                    .chars()
+                    .all(|character| character.is_alphanumeric() || "_-[]".contains(character))
+        })
+        && !is_literal(content)
+}
+
+fn split_path(content: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut in_brackets = false;
+    let mut start = 0;
+
+    for (offset, character) in content.char_indices() {
+        match character {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            '.' if !in_brackets => {
+                segments.push(&content[start..offset]);
+                start = offset + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&content[start..]);
+    segments
+}
+
+/// Rewrites `[...]` segments as index or key access. A data variable such as
+/// `[@index]` becomes access by the current loop's index or key binding.
+fn normalize_segments(
+    path: String,
+    stack: &[BlockContext],
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if !path.contains('[') {
+        return path;
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for (position, segment) in split_path(&path).into_iter().enumerate() {
+        let literal = segment
+            .strip_prefix('[')
+            .and_then(|segment| segment.strip_suffix(']'));
+        match literal {
+            Some(key)
+                if position > 0 && !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                normalized.push_str(&format!("[{}]", key));
+            }
+            Some(key) if position > 0 && key.starts_with('@') => {
+                let index = transform_data_variable(key.to_string(), stack, span, diagnostics);
+                normalized.push_str(&format!("[{}]", index));
+            }
+            Some(key) if position > 0 => {
+                normalized.push_str(&format!("[\"{}\"]", key.replace('"', "\\\"")));
+            }
+            _ => {
+                if position > 0 {
+                    normalized.push('.');
+                }
+                normalized.push_str(segment);
+            }
+        }
+    }
+    normalized
+}
+
+/// Splits an `#each` header into its iterable, alias, optional index and any
+/// `key=value` hash arguments, which may sit before or after the block params.
+fn parse_each(rest: &str, default_alias: &str) -> (String, String, Option<String>, Vec<String>) {
+    let (expr, params, trailing) = split_block_params(rest);
+    let mut arguments = split_arguments(expr);
+    let count = arguments.len();
+    // Without a positional iterable there is nothing to separate the hash from.
+    let hash_start = arguments
+        .iter()
+        .rposition(|argument| split_hash(argument).is_none())
+        .map_or(count, |last| last + 1);
+    let mut hash: Vec<String> = arguments
+        .split_off(hash_start)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    hash.extend(
+        split_arguments(trailing)
+            .into_iter()
+            .filter(|argument| split_hash(argument).is_some())
+            .map(str::to_string),
+    );
+    let expr = if hash_start == count {
+        expr.to_string()
+    } else {
+        arguments.join(" ")
+    };
+
+    let (alias, index) = match params.as_slice() {
+        [] => (default_alias.to_string(), None),
+        [alias, rest @ ..] => (
+            alias.to_string(),
+            rest.first().map(|index| index.to_string()),
+        ),
+    };
+    (expr, alias, index, hash)
+}
+
+/// The path a `key=` hash argument on `#each` names, relative to the loop
+/// alias: `"id"`, `id`, `this.id` and `item.id` all become `item.id`.
+fn loop_key(value: &str, alias: &str) -> String {
+    let path = value.trim_matches(['"', '\'']);
+    let path = path.strip_prefix("this.").unwrap_or(path);
+    if path.is_empty() || path == "this" {
+        alias.to_string()
+    } else if split_path(path)[0] == alias {
+        path.to_string()
+    } else {
+        format!("{alias}.{path}")
+    }
+}
+
+/// Splits `as |...|` block params off a block header, returning the
+/// expression before them, the params and whatever follows the closing `|`.
+fn split_block_params(rest: &str) -> (&str, Vec<&str>, &str) {
+    if let Some(pos) = block_params_start(rest) {
+        let after = &rest[pos + BLOCK_PARAMS.len()..];
+        if let Some(end) = after.find('|') {
+            return (
+                rest[..pos].trim(),
+                after[..end].split_whitespace().collect(),
+                after[end + 1..].trim(),
+            );
+        }
+    }
+
+    (rest.trim(), Vec::new(), "")
+}
+
+const BLOCK_PARAMS: &str = "as |";
+
+/// Where `as |` starts in a block header, if it names block params.
+fn block_params_start(rest: &str) -> Option<usize> {
+    // Block params may follow a line break in multi-line tags.
+    rest.match_indices(BLOCK_PARAMS)
+        .map(|(pos, _)| pos)
+        .find(|&pos| {
+            rest[..pos]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+        })
+}
+
+fn transform_expression(
+    tag: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let content = tag.trim().to_string();
+
+    if let Some(ExpressionHook(hook)) = &options.expression_hook
+        && let Some(rewritten) = hook(&content)
+    {
+        return rewritten;
+    }
+
+    if let [helper, _, ..] = split_arguments(&content).as_slice() {
+        if let Some(expression) =
+            transform_operator_helper(&content, span, stack, options, diagnostics)
+        {
+            return expression;
+        }
+        return transform_helper_call(&content, helper, span, stack, options, diagnostics);
+    }
+
+    if let Some(inner) = content
+        .strip_prefix('(')
+        .and_then(|content| content.strip_suffix(')'))
+    {
+        return transform_subexpression(inner.trim(), span, stack, options, diagnostics);
+    }
+
+    if let Some(path) = content.strip_prefix("@root.") {
+        return transform_root_path(path, stack, span, diagnostics);
+    }
+
+    if content.starts_with('@') {
+        return transform_data_variable(content, stack, span, diagnostics);
+    }
+
+    let path = resolve_path(content, tag, span, stack, options, diagnostics);
+    normalize_segments(path, stack, span, diagnostics)
+}
+
+fn resolve_path(
+    content: String,
+    tag: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let alias = innermost_each(stack).map(|(alias, _)| alias);
+    // `{{.}}` is another spelling of `{{this}}`.
+    let content = if content == "." {
+        "this".to_string()
+    } else {
+        content
+    };
+
+    if content.starts_with("../") {
+        if options.allow_parent {
+            // `../` segments are stripped first, then what is left is
+            // resolved like a path in the scope they lead to, so `../.`
+            // and `../this` both name that scope itself.
+            let mut stripped = content.as_str();
+            let mut count = 0;
+            while let Some(rest) = stripped.strip_prefix("../") {
+                stripped = rest;
+                count += 1;
+            }
+            if stripped == ".." {
+                stripped = "this";
+                count += 1;
+            }
+            let stripped = match stripped {
+                "." => "this",
+                _ => stripped.strip_prefix("./").unwrap_or(stripped),
+            };
+
+            let scopes: Vec<&BlockContext> =
+                stack.iter().filter(|context| context.is_scope()).collect();
+            if count > scopes.len() {
+                diagnostics.push(Diagnostic::new(Code::ParentScope, format!(
+                        "Parent scope access goes up {count} levels but only {} enclosing scopes exist",
+                        scopes.len()
+                    ), span));
+                return tag.to_string();
+            }
+
+            let base = match scopes.len() - count {
+                0 => None,
+                depth => match scopes[depth - 1] {
+                    BlockContext::Each { alias, .. } => Some(alias.as_str()),
+                    BlockContext::With { expr, .. } => expr.as_deref(),
+                    _ => None,
+                },
+            };
+            let resolved = join_scope_path(base, stripped);
+            diagnostics.push(Diagnostic::new(
+                Code::ParentScopeResolved,
+                format!("Resolved {count} parent scope segments (../) to {resolved}"),
+                span,
+            ));
+            if base.is_none() && resolved == "this" {
+                diagnostics.push(Diagnostic::new(
+                    Code::ParentScopeThis,
+                    format!(
+                        "{content} climbs to the top-level context, which has no name in \
+                         Sline; it became `this`, which may not mean the top level inside a block"
+                    ),
+                    span,
+                ));
+            }
+            return resolved;
+        } else {
+            diagnostics.push(Diagnostic::new(
+                Code::ParentScope,
+                "Parent scope access (../) is not supported in Sline".to_string(),
+                span,
+            ));
+            return tag.to_string();
+        }
+    }
+
+    // A path starting with a name bound by an enclosing block refers to that
+    // binding, not to a property of the innermost `#with` context.
+    let head = content.split(['.', '[']).next().unwrap_or_default();
+    if let Some(binding) = stack
+        .iter()
+        .rev()
+        .filter(|context| context.is_scope())
+        .find(|context| binds(context, head))
+    {
+        return match binding {
+            BlockContext::With {
+                expr: Some(expr),
+                alias,
+                ..
+            } => resolve_with(content, expr, alias.as_deref()),
+            _ => content,
+        };
+    }
+
+    if let Some(BlockContext::With {
+        expr: Some(expr),
+        alias: with_alias,
+        ..
+    }) = innermost_scope(stack)
+    {
+        return resolve_with(content, expr, with_alias.as_deref());
+    }
+
+    if let Some(alias) = alias {
+        if content == "this" {
+            return alias.to_string();
+        }
+        if let Some(rest) = content.strip_prefix("this.") {
+            return format!("{}.{}", alias, rest);
+        }
+        if let Some(rest) = content.strip_prefix("./") {
+            return format!("{}.{}", alias, rest);
+        }
+        // A leading literal segment such as `[0]` indexes the current item.
+        if content.starts_with('[') {
+            return format!("{}.{}", alias, content);
+        }
+    } else {
+        if content == "this" {
+            diagnostics.push(Diagnostic::new(
+                Code::NoEachContext,
+                "Found {{this}} without an each context".to_string(),
+                span,
+            ));
+            return content;
+        }
+        if let Some(rest) = content.strip_prefix("this.") {
+            return rest.to_string();
+        }
+        if let Some(rest) = content.strip_prefix("./") {
+            return rest.to_string();
+        }
+    }
+
+    content
+}
+
+fn join_scope_path(base: Option<&str>, path: &str) -> String {
+    let rest = if path == "this" {
+        ""
+    } else {
+        path.strip_prefix("this.").unwrap_or(path)
+    };
+    match (base, rest) {
+        (Some(base), "") => base.to_string(),
+        (Some(base), rest) => format!("{}.{}", base, rest),
+        (None, "") => "this".to_string(),
+        (None, rest) => rest.to_string(),
+    }
+}
+
+fn transform_helper_call(
+    content: &str,
+    helper: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if helper == "lookup" && !options.helpers.contains_key(helper) {
+        return transform_lookup(content, span, stack, options, diagnostics);
+    }
+
+    let mut call = match options.helpers.get(helper) {
+        Some(mapped) => mapped.clone(),
+        None => {
+            if !BUILTIN_HELPERS.contains(&helper) {
+                let diagnostic = Diagnostic::new(
+                    Code::UnknownHelper,
+                    format!("Unknown helper: {}", helper.trim_start_matches('#')),
+                    span,
+                );
+                diagnostics.push(if options.fail_unknown_helpers {
+                    Diagnostic {
+                        level: Level::Error,
+                        ..diagnostic
+                    }
+                } else {
+                    diagnostic
+                });
+            }
+            helper.to_string()
+        }
+    };
+    // Arguments are rewritten in place so the spacing between them is kept.
+    let mut copied = helper.len();
+    for argument in split_arguments(content).into_iter().skip(1) {
+        let start = argument.as_ptr() as usize - content.as_ptr() as usize;
+        call.push_str(&content[copied..start]);
+        copied = start + argument.len();
+        match split_hash(argument) {
+            Some((key, value)) => {
+                call.push_str(key);
+                call.push('=');
+                call.push_str(&transform_argument(
+                    value,
+                    span,
+                    stack,
+                    options,
+                    diagnostics,
+                ));
+            }
+            None => call.push_str(&transform_argument(
+                argument,
+                span,
+                stack,
+                options,
+                diagnostics,
+            )),
+        }
+    }
+    call.push_str(&content[copied..]);
+    call
+}
+
+/// Converts the inside of a `(helper arg ...)` subexpression. Its first
+/// token is always a helper, even when it takes no arguments.
+fn transform_subexpression(
+    inner: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let Some(&helper) = split_arguments(inner).first() else {
+        return "()".to_string();
+    };
+    if let Some(conditional) = transform_inline_if(inner, span, stack, options, diagnostics) {
+        return conditional;
+    }
+    if let Some(expression) = transform_operator_helper(inner, span, stack, options, diagnostics) {
+        return format!("({expression})");
+    }
+    let call = transform_helper_call(inner, helper, span, stack, options, diagnostics);
+    format!("({})", call)
+}
+
+/// Converts an inline `(if cond yes no)` or `(unless cond no yes)` into a
+/// Sline `(cond ? yes : no)`, with `""` for a missing last branch as in
+/// Handlebars. Returns `None` for other subexpressions, hash arguments, or
+/// when the helper is mapped in `helpers`.
+fn transform_inline_if(
+    inner: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<String> {
+    let arguments = split_arguments(inner);
+    let (&helper, arguments) = arguments.split_first()?;
+    if !matches!(helper, "if" | "unless")
+        || options.helpers.contains_key(helper)
+        || !(2..=3).contains(&arguments.len())
+        || arguments
+            .iter()
+            .any(|argument| split_hash(argument).is_some())
+    {
+        return None;
+    }
+
+    let condition = transform_condition(arguments[0], span, stack, options, diagnostics);
+    let mut branch = |index: usize| match arguments.get(index) {
+        Some(argument) => transform_argument(argument, span, stack, options, diagnostics),
+        None => "\"\"".to_string(),
+    };
+    let (truthy, falsy) = if helper == "if" {
+        (branch(1), branch(2))
+    } else {
+        let falsy = branch(1);
+        (branch(2), falsy)
+    };
+    Some(format!("({condition} ? {truthy} : {falsy})"))
+}
+
+fn transform_lookup(
+    content: &str,
+    span: Span,
+    stack: &[BlockContext],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let [_, target, key] = split_arguments(content)[..] else {
+        diagnostics.push(Diagnostic::new(
+            Code::UnconvertedLookup,
+            "lookup expects exactly two arguments; left as-is".to_string(),
+            span,
+        ));
+        return content.to_string();
+    };
+    if key.starts_with('(') || target.starts_with('(') {
+        diagnostics.push(Diagnostic::new(
+            Code::UnconvertedLookup,
+            "lookup with a subexpression argument has no Sline equivalent; left as-is".to_string(),
+            span,
+        ));
+        return content.to_string();
+    }
+
+    let target = transform_argument(target, span, stack, options, diagnostics);
+    let key = transform_argument(key, span, stack, options, diagnostics);
+    format!("{}[{}]", target, key)
+}
+
+fn transform_root_path(
+    path: &str,
+    stack: &[BlockContext],
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let head = split_path(path)[0];
+    let shadowed = stack.iter().any(|context| binds(context, head));
+    if shadowed {
+        diagnostics.push(Diagnostic::new(
+            Code::RootShadowed,
+            format!("@root.{path} is shadowed by the block param {head}"),
+            span,
+        ));
+    }
+    normalize_segments(path.to_string(), stack, span, diagnostics)
+}
+
+/// Resolves `@../index` or `@../key` to a block param of `each`, the
+/// enclosing loop its `../` segments climb to.
+fn transform_parent_data_variable(
+    content: &str,
+    name: &str,
+    each: Option<&BlockContext>,
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let Some(BlockContext::Each {
+        index,
+        object,
+        key_used,
+        index_used,
+        ..
+    }) = each
+    else {
+        diagnostics.push(Diagnostic::new(
+            Code::ParentDataVariable,
+            format!("{content} has no enclosing loop at that depth"),
+            span,
+        ));
+        return content.to_string();
+    };
+    let binding = match name {
+        "index" if !object && !key_used.get() => index.as_ref().inspect(|_| index_used.set(true)),
+        "index" => None,
+        "key" => index.as_ref().inspect(|_| key_used.set(true)),
+        _ => {
+            diagnostics.push(Diagnostic::new(
+                Code::ParentDataVariable,
+                format!("{content} has no Sline equivalent; only the index or key of an enclosing loop can be named"),
+                span,
+            ));
+            return content.to_string();
+        }
+    };
+    match binding {
+        Some(binding) => binding.to_string(),
+        None => {
+            diagnostics.push(Diagnostic::new(
+                Code::ParentDataVariable,
+                format!("{content} needs a block param on the enclosing loop; name it with `as |item {name}|` or use --always-index"),
+                span,
+            ));
+            content.to_string()
+        }
+    }
+}
+
+fn transform_data_variable(
+    content: String,
+    stack: &[BlockContext],
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    if let Some(name) = content.strip_prefix("@../") {
+        let mut name = name;
+        let mut depth = 1;
+        while let Some(rest) = name.strip_prefix("../") {
+            name = rest;
+            depth += 1;
+        }
+        let each = stack
+            .iter()
+            .rev()
+            .filter(|context| matches!(context, BlockContext::Each { empty: false, .. }))
+            .nth(depth);
+        return transform_parent_data_variable(&content, name, each, span, diagnostics);
+    }
+
+    let each = stack.iter().rev().find_map(|context| match context {
+        BlockContext::Each {
+            index,
+            object,
+            empty: false,
+            key_used,
+            index_used,
+            ..
+        } => Some((index.as_deref(), *object, key_used, index_used)),
+        _ => None,
+    });
+    let Some((index, object, key_used, index_used)) = each else {
+        diagnostics.push(Diagnostic::new(
+            Code::NoEachContext,
+            format!("Found {{{{{content}}}}} without an each context"),
+            span,
+        ));
+        return content;
+    };
+
+    match content.as_str() {
+        "@index" if object || key_used.get() => "forloop.index0".to_string(),
+        "@index" => match index {
+            Some(index) => {
+                index_used.set(true);
+                index.to_string()
+            }
+            None => "forloop.index0".to_string(),
+        },
+        "@first" => "forloop.first".to_string(),
+        "@last" => "forloop.last".to_string(),
+        "@key" => match index {
+            Some(key) => {
+                if index_used.get() && !key_used.get() {
+                    diagnostics.push(Diagnostic::new(
+                        Code::IndexAndKey,
+                        format!("@key uses {key}, which an earlier @index in this loop was converted to; use forloop.index0 for that @index"),
+                        span,
+                    ));
+                }
+                key_used.set(true);
+                key.to_string()
+            }
+            None => {
+                diagnostics.push(Diagnostic::new(Code::MissingKeyBinding, "@key needs a key binding; name it with `as |value key|` or use --each-as-object".to_string(), span));
+                content
+            }
+        },
+        _ => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(input: &str) -> (String, Vec<Code>) {
+        convert_with(input, &Options::default())
+    }
+
+    fn convert_with(input: &str, options: &Options) -> (String, Vec<Code>) {
+        let (output, diagnostics) = transpile(input, options);
+        let codes = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        (output, codes)
+    }
+
+    #[test]
+    fn sections_named_after_a_keyword_are_not_keyword_blocks() {
+        for name in ["ifActive", "eachItem", "unlessX"] {
+            let (output, codes) = convert(&format!("{{{{#{name}}}}}x{{{{/{name}}}}}"));
+            assert_eq!(output, format!("{{{{ #if {name} }}}}x{{{{ /if }}}}"));
+            assert_eq!(codes, [Code::SectionAsIf]);
+        }
+    }
+
+    #[test]
+    fn sections_named_after_with_are_not_with_blocks() {
+        let (output, codes) = convert("{{#withdrawals}}x{{/withdrawals}}");
+        assert_eq!(output, "{{ #if withdrawals }}x{{ /if }}");
+        assert_eq!(codes, [Code::SectionAsIf]);
+    }
+
+    /// Templates built from pieces that mix multi-byte characters with
+    /// mustache syntax, from a fixed seed so failures reproduce.
+    fn generated_inputs(count: usize) -> impl Iterator<Item = String> {
+        const PIECES: &[&str] = &[
+            "{{", "}}", "{{{", "}}}", "\\", "é", "日本", "😀", "\u{feff}", "~", "#", "/", "!--",
+            "--", "^", ">", "&", "\"", "'", "(", ")", " ", "\n", "\r\n", "each", "if", "else",
+            "this", "../", "@index", "as |a b|", "x", "=",
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        (0..count).map(move |_| {
+            let length = next() % 24;
+            (0..length).map(|_| PIECES[next() % PIECES.len()]).collect()
+        })
+    }
+
+    #[test]
+    fn arbitrary_utf8_with_mustaches_never_panics() {
+        let options = [
+            Options::default(),
+            Options {
+                allow_parent: true,
+                standalone_whitespace: true,
+                ..Options::default()
+            },
+        ];
+        for input in generated_inputs(20_000) {
+            for options in &options {
+                transpile(&input, options);
+            }
+            check_syntax(&input);
+        }
+    }
+
+    #[test]
+    fn case_insensitive_blocks_lowercase_keywords_only() {
+        let options = Options {
+            case_insensitive_blocks: true,
+            ..Options::default()
+        };
+        let (output, codes) = convert_with(
+            "{{#EACH Items}}{{#if this.On}}a{{Else If this.Off}}b{{/If}}{{/EACH}}",
+            &options,
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in Items }}{{ #if item.On }}a{{ else if item.Off }}b{{ /if }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::KeywordCase; 4]);
+    }
+
+    #[test]
+    fn keyword_case_is_kept_without_the_option() {
+        let (output, codes) = convert("{{#EACH items}}x{{/EACH}}");
+        assert_eq!(output, "{{ #EACH items }}x{{ /EACH }}");
+        assert!(!codes.contains(&Code::KeywordCase));
+    }
+
+    #[test]
+    fn triple_braces_are_spaced_like_double_braces() {
+        assert_eq!(convert("{{{ x }}}").0, "{{{ x }}}");
+        assert_eq!(convert("{{{x}}}").0, "{{{ x }}}");
+        assert_eq!(convert("{{{ user.name }}}").0, "{{{ user.name }}}");
+        let options = Options {
+            preserve_spacing: true,
+            ..Options::default()
+        };
+        assert_eq!(convert_with("{{{x}}}", &options).0, "{{{x}}}");
+        assert_eq!(convert_with("{{{ x }}}", &options).0, "{{{ x }}}");
+    }
+
+    #[test]
+    fn crossed_nesting_closes_the_open_blocks_in_order() {
+        let (output, diagnostics) =
+            transpile("{{#if a}}{{#each b}}{{/if}}{{/each}}", &Options::default());
+        assert_eq!(output, "{{ #if a }}{{ #for item in b }}{{ /for }}{{ /if }}");
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Mismatched closing tag: expected /each, found /if (each opened at 1:10)",
+                "Mismatched closing tag: expected /if, found /each (if opened at 1:1)",
+            ]
+        );
+        assert!(
+            diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.code == Code::MismatchedClose)
+        );
+    }
+
+    #[test]
+    fn same_line_blocks_are_not_trimmed_or_reindented() {
+        let options = Options {
+            standalone_whitespace: true,
+            indent: Some(2),
+            ..Options::default()
+        };
+        let input = "<p>\n  before {{#if a}}x{{/if}} after\n</p>\n";
+        assert_eq!(
+            convert_with(input, &options),
+            (
+                "<p>\n  before {{ #if a }}x{{ /if }} after\n</p>\n".to_string(),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn subexpression_iterables_are_converted() {
+        let (output, codes) =
+            convert("{{#each (sortBy users \"name\") as |u|}}{{u.name}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for u in (sortBy users \"name\") }}{{ u.name }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::UnknownHelper]);
+
+        let (output, _) = convert("{{#each (filter this.items) as |i|}}{{i}}{{/each}}");
+        assert_eq!(output, "{{ #for i in (filter items) }}{{ i }}{{ /for }}");
+
+        let options = Options {
+            helpers: HashMap::from([("sortBy".to_string(), "sort_by".to_string())]),
+            ..Options::default()
+        };
+        let (output, codes) = convert_with(
+            "{{#each (sortBy users \"name\") as |u|}}{{/each}}",
+            &options,
+        );
+        assert_eq!(output, "{{ #for u in (sort_by users \"name\") }}{{ /for }}");
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn escaped_mustaches_pass_through_literally() {
+        assert_eq!(
+            convert(r"a \{{foo}} b"),
+            (r"a {{foo}} b".to_string(), vec![])
+        );
+        assert_eq!(
+            convert(r"a \\{{foo}} b"),
+            (r"a \{{ foo }} b".to_string(), vec![])
+        );
+        assert_eq!(
+            convert(r"a \ b {{foo}}"),
+            (r"a \ b {{ foo }}".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn raw_blocks_are_copied_verbatim() {
+        let (output, codes) = convert("{{{{raw}}}} {{x}} {{{y}}} {{#if a}} {{{{/raw}}}} {{z}}");
+        assert_eq!(
+            output,
+            "{{ #raw }} {{x}} {{{y}}} {{#if a}} {{ /raw }} {{ z }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn else_if_and_else_unless_chain() {
+        let (output, codes) = convert("{{#if a}}A{{else if b}}B{{else unless c}}C{{else}}D{{/if}}");
+        assert_eq!(
+            output,
+            "{{ #if a }}A{{ else if b }}B{{ else if !(c) }}C{{ else }}D{{ /if }}"
+        );
+        assert!(codes.is_empty());
+
+        let (output, _) = convert("{{#each items}}{{#if a}}{{else if this.b}}{{/if}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in items }}{{ #if a }}{{ else if item.b }}{{ /if }}{{ /for }}"
+        );
+    }
+
+    #[test]
+    fn unclosed_blocks_name_the_block_and_its_offset() {
+        let input = "{{#each items}}{{#if a}}";
+        let expected = [
+            "Unclosed block: each (opened at offset 0)",
+            "Unclosed block: if (opened at offset 15)",
+        ];
+        let (_, diagnostics) = transpile(input, &Options::default());
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, expected);
+        assert!(diagnostics.iter().all(|d| d.code == Code::UnclosedBlock));
+
+        let diagnostics = check_syntax(input);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, expected);
+    }
+
+    #[test]
+    fn whitespace_control_markers_are_kept() {
+        let (output, codes) = convert("{{~foo~}} {{~#each x~}} a {{/each~}} {{{~ y ~}}}");
+        assert_eq!(
+            output,
+            "{{~ foo ~}} {{~ #for item in x ~}} a {{ /for ~}} {{{~ y ~}}}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn nested_comment_blocks_are_consumed_whole() {
+        let (output, codes) = convert("{{#comment}} a {{#comment}} b {{/comment}} c {{/comment}}d");
+        assert_eq!(output, "{{!-- a {{#comment}} b {{/comment}} c --}}d");
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn quoted_arguments_may_contain_braces() {
+        let (output, codes) = convert(r#"{{t "Price: }} today"}} {{t 'a {{ b'}} {{t 'c }} d'}}"#);
+        assert_eq!(
+            output,
+            r#"{{ t "Price: }} today" }} {{ t 'a {{ b' }} {{ t 'c }} d' }}"#
+        );
+        assert_eq!(codes, [Code::UnknownHelper; 3]);
+
+        let (output, _) = convert(r#"{{#comment}}{{t "{{/comment}}"}}{{/comment}}x"#);
+        assert_eq!(output, r#"{{!--{{t "{{/comment}}"}}--}}x"#);
+    }
+
+    #[test]
+    fn parent_scopes_resolve_to_enclosing_aliases() {
+        let options = Options {
+            allow_parent: true,
+            ..Options::default()
+        };
+        let (output, codes) = convert_with(
+            "{{#each a as |x|}}{{#each x.b as |y|}}{{../n}}{{../../m}}{{/each}}{{/each}}",
+            &options,
+        );
+        assert_eq!(
+            output,
+            "{{ #for x in a }}{{ #for y in x.b }}{{ x.n }}{{ m }}{{ /for }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::ParentScopeResolved; 2]);
+
+        let (output, codes) = convert_with(
+            "{{#each a as |x|}}{{#each x.b as |y|}}{{#each y.c as |z|}}{{../n}}{{../../m}}{{../../../r}}{{/each}}{{/each}}{{/each}}",
+            &options,
+        );
+        assert_eq!(
+            output,
+            "{{ #for x in a }}{{ #for y in x.b }}{{ #for z in y.c }}{{ y.n }}{{ x.m }}{{ r }}{{ /for }}{{ /for }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::ParentScopeResolved; 3]);
+
+        let (_, diagnostics) = transpile("{{#each a}}{{../../m}}{{/each}}", &options);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "Parent scope access goes up 2 levels but only 1 enclosing scopes exist"
+        );
+    }
+
+    #[test]
+    fn bracket_segments_become_key_and_index_access() {
+        let (output, codes) = convert(
+            "{{user.[first name]}} {{items.[0]}} {{user.[first-name].value}}{{#each items}}{{this.[0]}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ user[\"first name\"] }} {{ items[0] }} {{ user[\"first-name\"].value }}{{ #for item in items }}{{ item[0] }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn if_and_unless_require_a_condition() {
+        for input in ["{{#if}}{{/if}}", "{{#if   }}{{/if}}"] {
+            let (_, diagnostics) = transpile(input, &Options::default());
+            assert_eq!(diagnostics.len(), 1, "{input}");
+            assert_eq!(diagnostics[0].code, Code::MissingCondition);
+            assert_eq!(diagnostics[0].message, "#if requires a condition");
+        }
+        assert_eq!(
+            convert("{{#if x}}{{/if}}"),
+            ("{{ #if x }}{{ /if }}".to_string(), vec![])
+        );
+
+        let (_, diagnostics) = transpile("{{#unless}}{{/unless}}", &Options::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "#unless requires a condition");
+    }
+
+    #[test]
+    fn unless_negates_its_condition_once() {
+        let (output, codes) = convert(
+            "{{#unless x.y}}{{/unless}}{{#unless (foo bar)}}{{/unless}}{{#unless a || b}}{{/unless}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #if !(x.y) }}{{ /if }}{{ #if !(foo bar) }}{{ /if }}{{ #if !(a || b) }}{{ /if }}"
+        );
+        assert_eq!(codes, [Code::UnknownHelper]);
+
+        let (output, codes) =
+            convert("{{#each items}}{{#unless this.a}}{{else}}{{/unless}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in items }}{{ #if !(item.a) }}{{ else }}{{ /if }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::UnlessElse]);
+    }
+
+    #[test]
+    fn comment_blocks_follow_the_comment_style() {
+        for (style, expected) in [
+            (CommentStyle::Handlebars, "a{{!-- x --}}b"),
+            (CommentStyle::Sline, "a{{#comment}} x {{/comment}}b"),
+            (CommentStyle::Strip, "ab"),
+        ] {
+            let options = Options {
+                comment_style: style,
+                ..Options::default()
+            };
+            let (output, codes) = convert_with("a{{#comment}} x {{/comment}}b", &options);
+            assert_eq!(output, expected, "{style:?}");
+            assert!(codes.is_empty());
+
+            let (output, codes) = convert_with("a{{#comment}} x", &options);
+            assert_eq!(output, "a{{#comment}} x", "{style:?}");
+            assert_eq!(codes, [Code::UnclosedBlock]);
+        }
+    }
+
+    #[test]
+    fn each_hash_arguments_are_dropped_from_the_header() {
+        let (output, codes) = convert("{{#each items limit=10}}{{this}}{{/each}}");
+        assert_eq!(output, "{{ #for item in items }}{{ item }}{{ /for }}");
+        assert_eq!(codes, [Code::DroppedHashArguments]);
+
+        let (output, codes) = convert("{{#each items as |x| reverse=true}}{{x}}{{/each}}");
+        assert_eq!(output, "{{ #for x in items }}{{ x }}{{ /for }}");
+        assert_eq!(codes, [Code::DroppedHashArguments]);
+    }
+
+    #[test]
+    fn inverted_sections_become_negated_ifs() {
+        let (output, codes) = convert("{{^items}}empty{{/items}}");
+        assert_eq!(output, "{{ #if !(items) }}empty{{ /if }}");
+        assert_eq!(codes, [Code::SectionAsIf]);
+
+        let (output, codes) = convert("{{^items}}empty{{^}}full{{/items}}");
+        assert_eq!(output, "{{ #if !(items) }}empty{{ else }}full{{ /if }}");
+        assert_eq!(codes, [Code::SectionAsIf]);
+    }
+
+    #[test]
+    fn else_inside_a_mismatched_block_reports_once() {
+        let (output, diagnostics) = transpile("{{#each x}}a{{else}}b{{/if}}", &Options::default());
+        assert_eq!(output, "{{ #for item in x }}a{{ else }}b{{ /for }}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::MismatchedClose);
+    }
+
+    #[test]
+    fn standalone_lines_lose_their_whitespace() {
+        let options = Options {
+            standalone_whitespace: true,
+            ..Options::default()
+        };
+        let input = "<ul>\n  {{#each items}}\n  <li>{{this}}</li>\n  {{/each}}\n</ul>\n";
+        let (output, codes) = convert_with(input, &options);
+        assert_eq!(
+            output,
+            "<ul>\n{{ #for item in items }}  <li>{{ item }}</li>\n{{ /for }}</ul>\n"
+        );
+        assert!(codes.is_empty());
+
+        let (output, _) = convert_with("a\n  {{!-- c --}}  \nb {{#if x}}\n{{/if}}\n", &options);
+        assert_eq!(output, "a\n{{!-- c --}}b {{ #if x }}\n{{ /if }}");
+
+        let (output, _) = convert(input);
+        assert_eq!(
+            output,
+            "<ul>\n  {{ #for item in items }}\n  <li>{{ item }}</li>\n  {{ /for }}\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn path_like_partial_names_are_quoted() {
+        let (output, codes) =
+            convert("{{> components/button}}{{> nav.header}}{{> my-partial}}{{> \"quoted/name\"}}");
+        assert_eq!(
+            output,
+            "{{ include \"components/button\" }}{{ include \"nav.header\" }}{{ include \"my-partial\" }}{{ include \"quoted/name\" }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn always_index_binds_a_loop_index() {
+        let options = Options {
+            always_index: Some("index".to_string()),
+            ..Options::default()
+        };
+        let (output, codes) = convert_with("{{#each items}}{{@index}}{{/each}}", &options);
+        assert_eq!(
+            output,
+            "{{ #for item, index in items }}{{ index }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let (output, codes) =
+            convert_with("{{#each items as |x i|}}{{i}}{{@index}}{{/each}}", &options);
+        assert_eq!(output, "{{ #for x, i in items }}{{ i }}{{ i }}{{ /for }}");
+        assert!(codes.is_empty());
+
+        let (output, codes) = convert_with("{{#each items as |index|}}{{/each}}", &options);
+        assert_eq!(output, "{{ #for index, index2 in items }}{{ /for }}");
+        assert_eq!(codes, [Code::AliasShadowed]);
+
+        let (output, _) = convert("{{#each items}}{{@index}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in items }}{{ forloop.index0 }}{{ /for }}"
+        );
+    }
+
+    #[test]
+    fn range_subexpressions_become_range_loops() {
+        let (output, codes) = convert(
+            "{{#each (range 0 5)}}{{this}}{{/each}}{{#each (range start end) as |n|}}{{n}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in 0..5 }}{{ item }}{{ /for }}{{ #for n in start..end }}{{ n }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let options = Options {
+            range_helper: Some("span".to_string()),
+            ..Options::default()
+        };
+        let (output, codes) = convert_with(
+            "{{#each (span 0 5)}}{{/each}}{{#each (range 0 5)}}{{/each}}",
+            &options,
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in 0..5 }}{{ /for }}{{ #for item in (range 0 5) }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::UnknownHelper]);
+    }
+
+    #[test]
+    fn mustache_spacing_pads_double_and_triple_braces() {
+        let input = "{{x}} {{t a b}} {{{x}}} {{{t a}}}";
+        for (spacing, expected) in [
+            (
+                MustacheSpacing::Both,
+                "{{ x }} {{ t a b }} {{{ x }}} {{{ t a }}}",
+            ),
+            (MustacheSpacing::None, "{{x}} {{t a b}} {{{x}}} {{{t a}}}"),
+            (
+                MustacheSpacing::HelpersOnly,
+                "{{x}} {{ t a b }} {{{x}}} {{{ t a }}}",
+            ),
+        ] {
+            let options = Options {
+                mustache_spacing: spacing,
+                ..Options::default()
+            };
+            assert_eq!(convert_with(input, &options).0, expected, "{spacing:?}");
+        }
+    }
+
+    #[test]
+    fn decorators_are_left_as_is() {
+        let (output, codes) = convert("{{* inline \"x\"}} {{*foo}} {{* bar a=b}}");
+        assert_eq!(output, "{{ * inline \"x\" }} {{ *foo }} {{ * bar a=b }}");
+        assert_eq!(codes, [Code::Decorator; 3]);
+    }
+
+    #[test]
+    fn each_this_iterates_the_current_context() {
+        let (output, codes) = convert("{{#each this}}{{this}}{{/each}}");
+        assert_eq!(output, "{{ #for item in this }}{{ item }}{{ /for }}");
+        assert_eq!(codes, [Code::NoEachContext]);
+
+        let (output, codes) = convert("{{#each groups as |g|}}{{#each this}}{{/each}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for g in groups }}{{ #for item in g }}{{ /for }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let (output, _) = convert("{{#each groups}}{{#each this}}{{/each}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in groups }}{{ #for item2 in item }}{{ /for }}{{ /for }}"
+        );
+    }
+
+    #[test]
+    fn key_and_index_in_one_loop() {
+        let (output, codes) = convert("{{#each obj as |v k|}}{{@key}} {{@index}} {{k}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for v, k in obj }}{{ k }} {{ forloop.index0 }} {{ k }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let (output, codes) = convert("{{#each obj}}{{@key}} {{@index}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in obj }}{{ @key }} {{ forloop.index0 }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::MissingKeyBinding]);
+
+        let options = Options {
+            each_as_object: true,
+            ..Options::default()
+        };
+        let (output, codes) = convert_with("{{#each obj}}{{@key}} {{@index}}{{/each}}", &options);
+        assert_eq!(
+            output,
+            "{{ #for item, key in obj }}{{ key }} {{ forloop.index0 }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn unterminated_mustaches_do_not_stop_the_scan() {
+        let (output, diagnostics) = transpile("{{ foo } text {{bar}}", &Options::default());
+        assert_eq!(output, "{{ foo } text {{ bar }}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::UnterminatedTag);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(diagnostics[0].message, "Unterminated mustache at offset 0");
+    }
+
+    #[test]
+    fn comments_may_contain_braces() {
+        let (output, codes) = convert("{{!-- a }} b {{x}} --}}{{y}}{{! c {{z}} }}");
+        assert_eq!(output, "{{!-- a }} b {{x}} --}}{{ y }}{{! c {{z}} }}");
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn close_tag_typos_get_a_suggestion() {
+        let (output, diagnostics) = transpile("{{#each items}}x{{/eech}}", &Options::default());
+        assert_eq!(output, "{{ #for item in items }}x{{ /for }}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Mismatched closing tag: expected /each, found /eech (each opened at 1:1); did you mean {{/each}}?"
+        );
+
+        let (_, diagnostics) = transpile("{{#if a}}x{{/fi}}", &Options::default());
+        assert_eq!(
+            diagnostics[0].message,
+            "Mismatched closing tag: expected /if, found /fi (if opened at 1:1)"
+        );
+    }
+
+    #[test]
+    fn inline_if_subexpressions_become_ternaries() {
+        let (output, codes) = convert(
+            "{{foo class=(if active \"on\" \"off\")}} {{foo (unless a b c)}}{{#each xs}}{{foo (if this.on \"y\" \"n\")}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ foo class=(active ? \"on\" : \"off\") }} {{ foo (a ? c : b) }}{{ #for item in xs }}{{ foo (item.on ? \"y\" : \"n\") }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::UnknownHelper; 3]);
+    }
+
+    #[test]
+    fn unescaped_output_is_reported_at_the_chosen_level() {
+        for level in [Level::Error, Level::Warning] {
+            let options = Options {
+                unescaped: Some(level),
+                ..Options::default()
+            };
+            let (output, diagnostics) = transpile("{{{a}}} {{b}} {{{d}}}", &options);
+            assert_eq!(output, "{{{ a }}} {{ b }} {{{ d }}}");
+            assert_eq!(diagnostics.len(), 2);
+            assert!(
+                diagnostics
+                    .iter()
+                    .all(|d| d.code == Code::UnescapedOutput && d.level == level)
+            );
+        }
+        let (_, diagnostics) = transpile(
+            "{{& c}}",
+            &Options {
+                unescaped: Some(Level::Error),
+                ..Options::default()
+            },
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "Unescaped output of c; it is not HTML-escaped"
+        );
+        assert!(convert("{{{a}}} {{{d}}}").1.is_empty());
+    }
+
+    #[test]
+    fn partials_in_a_loop_receive_the_loop_item() {
+        let (output, codes) = convert(
+            "{{#each users}}{{> userRow}}{{> userRow this.profile}}{{> userRow other}}{{/each}}{{> userRow}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in users }}{{ include \"userRow\" item }}{{ include \"userRow\" item.profile }}{{ include \"userRow\" other }}{{ /for }}{{ include \"userRow\" }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn literal_conditions_are_kept_verbatim() {
+        let (output, codes) = convert(
+            "{{#each x}}{{#if \"x\"}}{{/if}}{{#if 0}}{{/if}}{{#if true}}{{/if}}{{#unless 1.5}}{{/unless}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in x }}{{ #if \"x\" }}{{ /if }}{{ #if 0 }}{{ /if }}{{ #if true }}{{ /if }}{{ #if !(1.5) }}{{ /if }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn parent_indexes_resolve_to_enclosing_loop_indexes() {
+        let (output, codes) = convert(
+            "{{#each a as |x i|}}{{#each b as |y j|}}{{#each c as |z k|}}{{@../index}} {{@../../index}}{{/each}}{{/each}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #for x, i in a }}{{ #for y, j in b }}{{ #for z, k in c }}{{ j }} {{ i }}{{ /for }}{{ /for }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let (_, diagnostics) = transpile("{{#each a}}{{@../index}}{{/each}}", &Options::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::ParentDataVariable);
+        assert_eq!(
+            diagnostics[0].message,
+            "@../index has no enclosing loop at that depth"
+        );
+
+        let input = "{{#each a}}{{#each b}}{{@../index}}{{/each}}{{/each}}";
+        let (_, diagnostics) = transpile(input, &Options::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+
+        let options = Options {
+            always_index: Some("index".to_string()),
+            ..Options::default()
+        };
+        let (output, _) = convert_with(input, &options);
+        assert_eq!(
+            output,
+            "{{ #for item, index in a }}{{ #for item2, index2 in b }}{{ index }}{{ /for }}{{ /for }}"
+        );
+    }
+
+    #[test]
+    fn stray_closing_tags_are_errors() {
+        let (output, diagnostics) =
+            transpile("a{{/if}}b{{/each}}{{/unless}}{{/foo}}", &Options::default());
+        assert_eq!(output, "a{{ /if }}b{{ /for }}{{ /if }}{{ /foo }}");
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Unexpected closing tag /if",
+                "Unexpected closing tag /each",
+                "Unexpected closing tag /unless",
+                "Unexpected closing tag /foo",
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.code == Code::UnexpectedClose));
+        assert_eq!(diagnostics[0].span.start, 1);
+    }
+
+    #[test]
+    fn each_key_hashes_resolve_against_the_alias() {
+        let (output, diagnostics) = transpile(
+            "{{#each items key=\"id\"}}{{/each}}{{#each items as |u| key=u.id}}{{/each}}",
+            &Options::default(),
+        );
+        assert_eq!(
+            output,
+            "{{ #for item in items }}{{ /for }}{{ #for u in items }}{{ /for }}"
+        );
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "#each key=\"id\" has no Sline equivalent and was dropped; key the items by item.id where they are rendered",
+                "#each key=u.id has no Sline equivalent and was dropped; key the items by u.id where they are rendered",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_tags_are_dropped_with_a_warning() {
+        let (output, diagnostics) = transpile("a{{}}b{{ }}c{{{}}}d", &Options::default());
+        assert_eq!(output, "abcd");
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|d| d.code == Code::EmptyTag
+            && d.level == Level::Warning
+            && d.message == "Empty mustache tag"));
+
+        let options = Options {
+            keep_empty_tags: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            convert_with("a{{}}b", &options),
+            ("a{{  }}b".to_string(), vec![Code::EmptyTag])
+        );
+    }
+
+    #[test]
+    fn operator_helpers_become_operators() {
+        let (output, codes) = convert(
+            "{{and a b}} {{foo (or a b) (not c) (eq a 1) (ne a b) (gt a 1) (gte a 1) (lt a 1) (lte a 1)}}",
+        );
+        assert_eq!(
+            output,
+            "{{ a && b }} {{ foo (a || b) (!c) (a == 1) (a != b) (a > 1) (a >= 1) (a < 1) (a <= 1) }}"
+        );
+        assert_eq!(codes, [Code::UnknownHelper]);
+
+        let (output, codes) = convert("{{#each xs}}{{#if (and (eq this.a 1) b)}}{{/if}}{{/each}}");
+        assert_eq!(
+            output,
+            "{{ #for item in xs }}{{ #if (item.a == 1) && b }}{{ /if }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let options = Options {
+            helpers: HashMap::from([("eq".to_string(), "equals".to_string())]),
+            ..Options::default()
+        };
+        let (output, codes) = convert_with("{{#if (eq a 1)}}{{/if}}", &options);
+        assert_eq!(output, "{{ #if (equals a 1) }}{{ /if }}");
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn blocks_inside_an_unclosed_comment_still_close() {
+        let input = "{{#comment}}{{#comment}}{{#comment}}x{{/comment}}y{{/comment}}";
+        let (output, codes) = convert(input);
+        assert_eq!(output, "{{#comment}}{{!--{{#comment}}x{{/comment}}y--}}");
+        assert_eq!(codes, [Code::UnclosedBlock]);
+        assert_eq!(check_syntax(input).len(), 1);
+
+        let (output, codes) = convert("{{{{raw}}}} a {{{{raw}}}} b {{{{/raw}}}}");
+        assert_eq!(output, "{{ #raw }} a {{{{raw}}}} b {{ /raw }}");
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn unclosed_openers_are_not_rescanned() {
+        for opener in ["{{#comment}} x\n", "{{{{raw}}}} x\n"] {
+            let input = opener.repeat(20_000);
+            let started = std::time::Instant::now();
+            let (output, diagnostics) = transpile(&input, &Options::default());
+            assert_eq!(check_syntax(&input).len(), 20_000);
+            let elapsed = started.elapsed();
+            assert_eq!(output, input);
+            assert_eq!(diagnostics.len(), 20_000);
+            assert!(elapsed.as_secs() < 5, "{opener:?} took {elapsed:?}");
+        }
+    }
+
+    #[test]
+    fn names_bound_outside_a_with_are_not_prefixed() {
+        let (output, codes) = convert(
+            "{{#each items as |it|}}{{#with it.user}}{{it.name}} {{name}}{{/with}}{{/each}}",
+        );
+        assert_eq!(
+            output,
+            "{{ #for it in items }}{{ #if it.user }}{{ it.name }} {{ it.user.name }}{{ /if }}{{ /for }}"
+        );
+        assert!(codes.is_empty());
+
+        let (output, codes) =
+            convert("{{#with a as |x|}}{{#with b}}{{x.n}} {{n}}{{/with}}{{/with}}");
+        assert_eq!(
+            output,
+            "{{ #if a }}{{ #if a.b }}{{ a.n }} {{ a.b.n }}{{ /if }}{{ /if }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn written_output_matches_the_segments() {
+        let options = [
+            Options::default(),
+            Options {
+                indent: Some(2),
+                keep_bom: true,
+                normalize_escaped_braces: true,
+                ..Options::default()
+            },
+        ];
+        let mut out = "stale".to_string();
+        let mut transpiler = Transpiler::new();
+        for input in generated_inputs(500) {
+            for options in &options {
+                let (segments, diagnostics) = transpile_detailed(&input, options);
+                let expected: String = segments
+                    .iter()
+                    .map(|segment| segment.text.as_str())
+                    .collect();
+                let written = transpile_into(&input, options, &mut out);
+                assert_eq!(written.len(), diagnostics.len());
+                assert_eq!(out, expected, "{input:?}");
+                assert_eq!(
+                    transpiler.transpile(&input, options).0,
+                    expected,
+                    "{input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converted_features_convert_as_documented() {
+        for feature in features() {
+            if feature.support != Support::Converted {
+                continue;
+            }
+            let example = feature
+                .example
+                .unwrap_or_else(|| panic!("{} has no example", feature.construct));
+            assert_eq!(
+                convert(&example.input),
+                (example.output, vec![]),
+                "{}",
+                feature.construct
+            );
+        }
+    }
+
+    #[test]
+    fn strip_comments_drops_every_comment_form() {
+        let options = Options {
+            strip_comments: true,
+            ..Options::default()
+        };
+        assert_eq!(convert_with("a {{! x }} b", &options).0, "a  b");
+        assert_eq!(convert_with("a {{!-- y }} --}} b", &options).0, "a  b");
+        assert_eq!(
+            convert_with("a {{#comment}} {{z}} {{/comment}} b", &options).0,
+            "a  b"
+        );
+        assert_eq!(convert_with("a\n  {{! line }}\nb", &options).0, "a\nb");
+    }
+
+    #[test]
+    fn debugging_helpers_are_removed() {
+        let (output, codes) =
+            convert("a {{log x}} b\n  {{log \"msg\" level=\"warn\"}}\nc {{debugger}}d");
+        assert_eq!(output, "a  b\nc d");
+        assert_eq!(codes, [Code::DroppedHelper; 3]);
+
+        let options = Options {
+            helpers: HashMap::from([("trace".to_string(), String::new())]),
+            ..Options::default()
+        };
+        let (output, codes) = convert_with("a\n{{trace x}}\nb", &options);
+        assert_eq!(output, "a\nb");
+        assert_eq!(codes, [Code::DroppedHelper]);
+    }
+
+    #[test]
+    fn a_leading_bom_is_dropped_unless_kept() {
+        assert_eq!(
+            convert("\u{feff}{{x}}\n"),
+            ("{{ x }}\n".to_string(), vec![])
+        );
+
+        let options = Options {
+            keep_bom: true,
+            ..Options::default()
+        };
+        assert_eq!(convert_with("\u{feff}{{x}}", &options).0, "\u{feff}{{ x }}");
+
+        // The mark does not stop the first line from standing alone.
+        let options = Options {
+            standalone_whitespace: true,
+            ..Options::default()
+        };
+        let input = "  {{#if a}}\n{{/if}}";
+        let expected = convert_with(input, &options);
+        assert_eq!(expected.0, "{{ #if a }}{{ /if }}");
+        assert_eq!(
+            convert_with(&format!("\u{feff}{input}"), &options),
+            expected
+        );
+    }
+
+    #[test]
+    fn segments_after_an_index_on_this_keep_their_path() {
+        assert_eq!(
+            convert("{{#each items}}{{this.[0].name}} {{./[0]}} {{./[0].name}}{{/each}}"),
+            (
+                "{{ #for item in items }}{{ item[0].name }} {{ item[0] }} {{ item[0].name }}{{ /for }}"
+                    .to_string(),
+                vec![]
+            )
+        );
+        assert_eq!(convert("{{this.[0].name}}").0, "{{ [0].name }}");
+    }
+
+    #[test]
+    fn bracketed_loop_variables_index_by_the_loop_binding() {
+        assert_eq!(
+            convert("{{#each a}}{{[@index]}} {{items.[@index].name}}{{/each}}"),
+            (
+                "{{ #for item in a }}{{ item[forloop.index0] }} {{ items[forloop.index0].name }}{{ /for }}"
+                    .to_string(),
+                vec![]
+            )
+        );
+        assert_eq!(
+            convert("{{#each a as |v k|}}{{m.[@key]}}{{/each}}"),
+            (
+                "{{ #for v, k in a }}{{ m[k] }}{{ /for }}".to_string(),
+                vec![]
+            )
+        );
+        let (_, codes) = convert("{{#each items.[@index]}}{{/each}}");
+        assert_eq!(codes, [Code::NoEachContext]);
+    }
+
+    #[test]
+    fn only_unrecognized_helpers_are_reported() {
+        let input = "{{lookup a b}} {{upper a}} {{foo a}}";
+        let (output, codes) = convert(input);
+        assert_eq!(output, "{{ a[b] }} {{ upper a }} {{ foo a }}");
+        assert_eq!(codes, [Code::UnknownHelper; 2]);
+
+        let options = Options {
+            helpers: HashMap::from([("upper".to_string(), "upcase".to_string())]),
+            ..Options::default()
+        };
+        let (output, diagnostics) = transpile(input, &options);
+        assert_eq!(output, "{{ a[b] }} {{ upcase a }} {{ foo a }}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Unknown helper: foo");
+        assert_eq!(diagnostics[0].level, Level::Warning);
+    }
+
+    #[test]
+    fn fail_unknown_helpers_reports_them_as_errors() {
+        let options = Options {
+            helpers: HashMap::from([("upper".to_string(), "upcase".to_string())]),
+            fail_unknown_helpers: true,
+            ..Options::default()
+        };
+        let (output, diagnostics) = transpile("{{upper a}} {{foo a}} {{#if a}}{{/if}}", &options);
+        assert_eq!(output, "{{ upcase a }} {{ foo a }} {{ #if a }}{{ /if }}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::UnknownHelper);
+        assert_eq!(diagnostics[0].level, Level::Error);
+    }
+
+    #[test]
+    fn entity_escaped_braces_pass_through_unless_normalized() {
+        let input =
+            "a &#123;&#123;x&#125;&#125; {{#each b}}&#x7b;&#x7B;y&rbrace;&rcub;{{/each}} &#123;c";
+        assert_eq!(
+            convert(input),
+            (
+                "a &#123;&#123;x&#125;&#125; {{ #for item in b }}&#x7b;&#x7B;y&rbrace;&rcub;{{ /for }} &#123;c"
+                    .to_string(),
+                vec![]
+            )
+        );
+
+        let options = Options {
+            normalize_escaped_braces: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            convert_with(input, &options),
+            (
+                "a {{ #raw }}{{x}}{{ /raw }} {{ #for item in b }}{{ #raw }}{{y}}{{ /raw }}{{ /for }} &#123;c"
+                    .to_string(),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn root_paths_skip_the_enclosing_aliases() {
+        assert_eq!(
+            convert("{{#each items}}{{@root.title}} {{this.name}}{{/each}}"),
+            (
+                "{{ #for item in items }}{{ title }} {{ item.name }}{{ /for }}".to_string(),
+                vec![]
+            )
+        );
+        assert_eq!(
+            convert(
+                "{{#with user as |u|}}{{#each u.posts as |p|}}{{@root.site.name}} {{p.title}}{{/each}}{{/with}}"
+            ),
+            (
+                "{{ #if user }}{{ #for p in user.posts }}{{ site.name }} {{ p.title }}{{ /for }}{{ /if }}"
+                    .to_string(),
+                vec![]
+            )
+        );
+        assert_eq!(
+            convert("{{#with user}}{{@root.title}} {{n}}{{/with}}").0,
+            "{{ #if user }}{{ title }} {{ user.n }}{{ /if }}"
+        );
+
+        let (output, codes) = convert("{{#each items as |it|}}{{@root.it}}{{/each}}");
+        assert_eq!(output, "{{ #for it in items }}{{ it }}{{ /for }}");
+        assert_eq!(codes, [Code::RootShadowed]);
+    }
+
+    #[test]
+    fn parent_segments_are_stripped_before_this_resolves() {
+        let options = Options {
+            allow_parent: true,
+            ..Options::default()
+        };
+        let input = "{{#each a}}{{#each b}}{{../this.name}} {{../../this}}{{/each}}{{/each}}";
+        let (output, diagnostics) = transpile(input, &options);
+        assert_eq!(
+            output,
+            "{{ #for item in a }}{{ #for item2 in b }}{{ item.name }} {{ this }}{{ /for }}{{ /for }}"
+        );
+        let codes: Vec<Code> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                Code::ParentScopeResolved,
+                Code::ParentScopeResolved,
+                Code::ParentScopeThis
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "Resolved 1 parent scope segments (../) to item.name"
+        );
+
+        let (output, _) = convert_with(
+            "{{#each a as |x|}}{{#each x.b as |y|}}{{../this.name}}{{/each}}{{/each}}",
+            &options,
+        );
+        assert_eq!(
+            output,
+            "{{ #for x in a }}{{ #for y in x.b }}{{ x.name }}{{ /for }}{{ /for }}"
+        );
+
+        let (output, codes) = convert(input);
+        assert_eq!(
+            output,
+            "{{ #for item in a }}{{ #for item2 in b }}{{ ../this.name }} {{ ../../this }}{{ /for }}{{ /for }}"
+        );
+        assert_eq!(codes, [Code::ParentScope; 2]);
+    }
+
+    #[test]
+    fn converting_the_fixture_output_again_changes_nothing() {
+        for output in [
+            include_str!("../example/test-converted.html"),
+            include_str!("../example/test-crlf-converted.html"),
+        ] {
+            assert_eq!(transpile(output, &Options::default()).0, output);
+        }
+        for input in [
+            include_str!("../example/test.html"),
+            include_str!("../example/test-crlf.html"),
+        ] {
+            let (once, _) = transpile(input, &Options::default());
+            assert_eq!(transpile(&once, &Options::default()).0, once);
+        }
+    }
+
+    #[test]
+    fn ampersand_output_is_unescaped_like_triple_braces() {
+        assert_eq!(
+            convert("{{&user.name}} {{& user.name }} {{#each a}}{{&this.x}}{{/each}}"),
+            (
+                "{{{ user.name }}} {{{ user.name }}} {{ #for item in a }}{{{ item.x }}}{{ /for }}"
+                    .to_string(),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn else_outside_a_block_is_an_error() {
+        let (output, diagnostics) = transpile("a {{else}} b", &Options::default());
+        assert_eq!(output, "a {{ else }} b");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::StrayElse);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "{{else}} outside of an #if, #unless or #each block"
+        );
+        assert_eq!(
+            (diagnostics[0].span.line, diagnostics[0].span.column),
+            (1, 3)
+        );
+
+        let (output, codes) = convert("{{#if a}}x{{/if}}{{else if b}}y");
+        assert_eq!(output, "{{ #if a }}x{{ /if }}{{ else if b }}y");
+        assert_eq!(codes, [Code::StrayElse]);
+    }
+
+    #[test]
+    fn only_plain_numbers_are_numeric_literals() {
+        for literal in ["0", "42", "-2", "1.5", "-0.25"] {
+            assert!(is_number(literal), "{literal}");
+        }
+        for name in [
+            "nan", "NaN", "inf", "infinity", "Infinity", "1e3", "+1", "1.", ".5", "-",
+        ] {
+            assert!(!is_number(name), "{name}");
+        }
+
+        let (output, codes) = convert("{{#with a}}{{nan}} {{inf}} {{infinity}} {{1.5}}{{/with}}");
+        assert_eq!(
+            output,
+            "{{ #if a }}{{ a.nan }} {{ a.inf }} {{ a.infinity }} {{ 1.5 }}{{ /if }}"
+        );
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn malformed_each_headers_are_errors() {
+        for (input, output, message) in [
+            (
+                "{{#each}}x{{/each}}",
+                "{{ #each }}x{{ /each }}",
+                "#each requires an iterable",
+            ),
+            (
+                "{{#each as |x|}}x{{/each}}",
+                "{{ #each as |x| }}x{{ /each }}",
+                "#each requires an iterable",
+            ),
+            (
+                "{{#each a as |x}}{{x}}{{/each}}",
+                "{{ #each a as |x }}{{ x }}{{ /each }}",
+                "#each block params are missing their closing |",
+            ),
+            (
+                "{{#each a as |x i k|}}{{x}}{{/each}}",
+                "{{ #for x, i in a }}{{ x }}{{ /for }}",
+                "#each binds at most an item and an index; dropped the block params k",
+            ),
+        ] {
+            let (converted, diagnostics) = transpile(input, &Options::default());
+            assert_eq!(converted, output);
+            assert_eq!(diagnostics.len(), 1, "{input}");
+            assert_eq!(diagnostics[0].code, Code::MalformedEach);
+            assert_eq!(diagnostics[0].level, Level::Error);
+            assert_eq!(diagnostics[0].message, message);
+        }
+    }
+}
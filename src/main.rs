@@ -1,88 +1,852 @@
+mod diff;
+mod glob;
+
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Level {
-    Warning,
-    Error,
-}
-
-#[derive(Debug, Clone)]
-struct Diagnostic {
-    level: Level,
-    message: String,
-}
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 
-#[derive(Debug, Clone)]
-struct Options {
-    allow_parent: bool,
-}
+use sline_transpiler::{
+    Code, CommentStyle, Diagnostic, FinalNewline, Level, MustacheSpacing, Options, Segment, Span,
+    Stats, Stream, annotate, check_syntax, features, json_string, transpile,
+    transpile_detailed_with_stats,
+};
 
-#[derive(Debug, Clone)]
-enum BlockContext {
-    Each { alias: String },
-}
+/// A template had errors under `--check`, or output would change.
+const EXIT_FAILURE: i32 = 1;
+/// Invalid arguments or configuration.
+const EXIT_USAGE: i32 = 2;
+/// A file or stream could not be read or written.
+const EXIT_IO: i32 = 3;
 
 fn main() {
     let config = match parse_args() {
         Ok(config) => config,
         Err(message) => {
             eprintln!("{message}");
-            std::process::exit(2);
+            std::process::exit(EXIT_USAGE);
         }
     };
 
-    let input_text = match read_input(&config) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Failed to read input: {err}");
-            std::process::exit(1);
+    let mut helpers = config.config_helpers.clone();
+    if let Some(ref path) = config.helpers {
+        match load_helpers(path) {
+            Ok(file_helpers) => helpers.extend(file_helpers),
+            Err(message) => {
+                eprintln!("{message}");
+                std::process::exit(EXIT_USAGE);
+            }
         }
-    };
+    }
 
     let options = Options {
         allow_parent: config.allow_parent,
+        preserve_spacing: config.no_reformat,
+        helpers,
+        each_as_object: config.each_as_object,
+        default_alias: config.default_alias.clone(),
+        always_index: config.always_index.clone(),
+        range_helper: config.range_helper.clone(),
+        indent: config.indent,
+        strip_comments: config.strip_comments,
+        keep_bom: config.keep_bom,
+        keep_empty_tags: config.keep_empty_tags,
+        comment_style: config.comment_style,
+        mustache_spacing: config.mustache_spacing,
+        normalize_escaped_braces: config.normalize_escaped_braces,
+        standalone_whitespace: config.standalone_whitespace,
+        fail_unknown_helpers: config.fail_unknown_helpers,
+        case_insensitive_blocks: config.case_insensitive_blocks,
+        unescaped: config.unescaped,
+        passthrough_blocks: config.passthrough_blocks.clone(),
+        expression_hook: None,
+    };
+
+    if config.list_diagnostics {
+        print!("{}", diagnostic_catalog(config.format));
+        std::process::exit(0);
+    }
+
+    if config.list_features {
+        print!("{}", feature_catalog(config.format));
+        std::process::exit(0);
+    }
+
+    if let Some(code) = config.explain {
+        print!("{}", explain(code, config.format));
+        std::process::exit(0);
+    }
+
+    if config.print_config {
+        eprint!(
+            "{}",
+            print_settings(&settings(&config, &options), config.format)
+        );
+        std::process::exit(0);
+    }
+
+    let jobs = match plan_jobs(&config) {
+        Ok(jobs) => jobs,
+        Err((code, message)) => {
+            eprintln!("{message}");
+            std::process::exit(code);
+        }
     };
-    let (output, diagnostics) = transpile(&input_text, &options);
 
-    if let Err(err) = write_output(&config, &output) {
+    if config.sourcemap.is_some() && jobs.len() != 1 {
+        eprintln!("--sourcemap needs exactly one input");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    // With --fail-fast, a file with an error stops workers from starting
+    // on the rest.
+    let failing = |outcome: &Result<Outcome, String>| match outcome {
+        Ok(outcome) => outcome.diagnostics.iter().any(|diagnostic| {
+            diagnostic.level == Level::Error && !config.allow.contains(&diagnostic.code)
+        }),
+        Err(_) => true,
+    };
+    let outcomes = run_parallel(
+        &jobs,
+        config.jobs,
+        |job| process(job, &config, &options),
+        |outcome| config.fail_fast && failing(outcome),
+    );
+
+    let color = match config.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    };
+    let mut reporter = Reporter::new(config.format, config.quiet, color);
+    let mut changed = false;
+    // Files that could not be read or written are reported like any other
+    // error, so the rest of the batch still gets converted.
+    let mut failed = false;
+    let mut stopped = false;
+    let mut manifest = Vec::new();
+    let mut counts = Stats::default().constructs().map(|(_, count)| count);
+    for (job, outcome) in jobs.iter().zip(outcomes) {
+        let Some(outcome) = outcome else {
+            break;
+        };
+        stopped = config.fail_fast && failing(&outcome);
+        let mut outcome = outcome.unwrap_or_else(|message| {
+            failed = true;
+            Outcome::failed(Code::Io, message)
+        });
+        if let Err(err) = io::stdout().write_all(outcome.stdout.as_bytes()) {
+            eprintln!("Failed to write output: {err}");
+            std::process::exit(EXIT_IO);
+        }
+        eprint!("{}", outcome.trace);
+        changed |= outcome.changed;
+        outcome
+            .diagnostics
+            .retain(|diagnostic| !config.allow.contains(&diagnostic.code));
+        if config.manifest.is_some() {
+            manifest.push(manifest_entry(job, &outcome.diagnostics));
+        }
+        if let Some(ref stats) = outcome.stats {
+            for (total, (_, count)) in counts.iter_mut().zip(stats.constructs()) {
+                *total += count;
+            }
+        }
+        let tags = outcome.stats.map(|stats| stats.tags_transformed);
+        reporter.report(job, outcome.diagnostics, tags);
+        if stopped {
+            break;
+        }
+    }
+    if let Err(err) = io::stdout().flush() {
         eprintln!("Failed to write output: {err}");
-        std::process::exit(1);
+        std::process::exit(EXIT_IO);
+    }
+    reporter.finish();
+
+    if config.count_only {
+        print!("{}", count_report(&counts, config.format));
+    }
+
+    if let Some(ref path) = config.manifest
+        && let Err(err) = fs::write(path, format!("[{}]\n", manifest.join(",")))
+    {
+        eprintln!("Failed to write manifest: {err}");
+        std::process::exit(EXIT_IO);
+    }
+
+    let has_failure = reporter.errors > 0 || (config.strict && reporter.warnings > 0);
+    if failed {
+        std::process::exit(EXIT_IO);
+    }
+    if stopped {
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let checking = config.check || config.check_only_syntax || config.check_idempotent;
+    if (has_failure && checking) || changed {
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    if let Some(max) = config.max_warnings
+        && reporter.warnings > max
+    {
+        eprintln!("Too many warnings: {} (limit {max})", reporter.warnings);
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+/// What one job produced, kept until every job has finished so results are
+/// reported in input order.
+struct Outcome {
+    diagnostics: Vec<Diagnostic>,
+    stats: Option<Stats>,
+    /// Text for stdout: the transpiled output, a diff, or a status line.
+    stdout: String,
+    /// `--verbose` trace of each transformed tag, for stderr.
+    trace: String,
+    changed: bool,
+}
+
+impl Outcome {
+    /// A job that produced nothing but the error `message`.
+    fn failed(code: Code, message: String) -> Self {
+        Outcome {
+            diagnostics: vec![Diagnostic::new(code, message, Span::default())],
+            stats: None,
+            stdout: String::new(),
+            trace: String::new(),
+            changed: false,
+        }
+    }
+}
+
+fn process(job: &Job, config: &Config, options: &Options) -> Result<Outcome, String> {
+    if config.stream {
+        return process_stream(options, config.final_newline, config.buffer_size);
+    }
+    let input_text = read_input(job).map_err(|err| format!("Failed to read input: {err}"))?;
+
+    if config.check_only_syntax {
+        return Ok(Outcome {
+            diagnostics: check_syntax(&input_text),
+            stats: None,
+            stdout: String::new(),
+            trace: String::new(),
+            changed: false,
+        });
+    }
+
+    let (segments, diagnostics, stats) = transpile_detailed_with_stats(&input_text, options);
+    let output: String = segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect();
+
+    if let Some(ref path) = config.sourcemap {
+        fs::write(path, sourcemap_json(&segments))
+            .map_err(|err| format!("Failed to write source map: {err}"))?;
+    }
+
+    let mut diagnostics = diagnostics;
+    if config.check_idempotent {
+        let (again, _) = transpile(&output, options);
+        if let Some(position) = output
+            .bytes()
+            .zip(again.bytes())
+            .position(|(first, second)| first != second)
+            .or((output.len() != again.len()).then(|| output.len().min(again.len())))
+        {
+            let line = output[..position].matches('\n').count() + 1;
+            diagnostics.push(Diagnostic::new(
+                Code::NotIdempotent,
+                format!("Transpiling the output again changes its line {line}"),
+                Span::default(),
+            ));
+        }
+    }
+    let mut output = if config.annotate {
+        annotate(&segments, &diagnostics)
+    } else {
+        output
+    };
+    config.final_newline.apply(&mut output);
+
+    if config.count_only {
+        return Ok(Outcome {
+            diagnostics,
+            stats: Some(stats),
+            stdout: String::new(),
+            trace: String::new(),
+            changed: false,
+        });
+    }
+
+    let mut outcome = Outcome {
+        diagnostics,
+        stats: Some(stats),
+        stdout: String::new(),
+        trace: String::new(),
+        changed: false,
+    };
+    if config.verbose {
+        outcome.trace = trace(job, &input_text, &segments);
+    }
+
+    if config.diff || config.dry_run {
+        let name = match job.input {
+            Some(ref path) => path.display().to_string(),
+            None => "<stdin>".to_string(),
+        };
+        if config.diff
+            && let Some(diff) = diff::unified(&name, &input_text, &output)
+        {
+            outcome.stdout.push_str(&diff);
+        }
+        if config.dry_run {
+            let status = if output == input_text {
+                "unchanged"
+            } else {
+                "would change"
+            };
+            outcome.stdout.push_str(&format!("{name}: {status}\n"));
+        }
+        outcome.changed = output != input_text;
+        return Ok(outcome);
+    }
+
+    if job.output.is_none() {
+        outcome.stdout = output;
+    } else {
+        write_output(job, &output, config.buffer_size)
+            .map_err(|err| format!("Failed to write output: {err}"))?;
+    }
+    Ok(outcome)
+}
+
+/// Converts stdin a chunk at a time for `--stream`, writing each part of
+/// the output to stdout as soon as it is final. The end of each part, from
+/// its last character on, is held back until more output follows so that
+/// `final_newline` can be applied to the end of the whole output. Output
+/// is buffered up to `buffer_size` bytes and flushed after every read.
+fn process_stream(
+    options: &Options,
+    final_newline: FinalNewline,
+    buffer_size: usize,
+) -> Result<Outcome, String> {
+    let mut stream = Stream::new(options);
+    let mut stdin = io::stdin().lock();
+    let mut stdout = BufWriter::with_capacity(buffer_size, io::stdout().lock());
+    let mut chunk = vec![0; 64 * 1024];
+    // Bytes of a character split across two reads.
+    let mut partial = Vec::new();
+    let mut output = String::new();
+    let mut pending = String::new();
+    loop {
+        let read = match stdin.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(format!("Failed to read input: {err}")),
+        };
+        partial.extend_from_slice(&chunk[..read]);
+        let valid = match std::str::from_utf8(&partial) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => {
+                return Err("Failed to read input: stream did not contain valid UTF-8".to_string());
+            }
+        };
+        let text = std::str::from_utf8(&partial[..valid]).expect("validated above");
+        stream.feed(text, &mut output);
+        partial.drain(..valid);
+        let held = held_end(&output, final_newline);
+        if held > 0 {
+            stdout
+                .write_all(pending.as_bytes())
+                .and_then(|()| stdout.write_all(&output.as_bytes()[..held]))
+                .and_then(|()| stdout.flush())
+                .map_err(|err| format!("Failed to write output: {err}"))?;
+            pending.clear();
+        }
+        pending.push_str(&output[held..]);
+        output.clear();
+    }
+    if !partial.is_empty() {
+        return Err("Failed to read input: stream did not contain valid UTF-8".to_string());
+    }
+
+    let (diagnostics, stats) = stream.finish(&mut output);
+    pending.push_str(&output);
+    final_newline.apply(&mut pending);
+    stdout
+        .write_all(pending.as_bytes())
+        .and_then(|()| stdout.flush())
+        .map_err(|err| format!("Failed to write output: {err}"))?;
+    Ok(Outcome {
+        diagnostics,
+        stats: Some(stats),
+        stdout: String::new(),
+        trace: String::new(),
+        changed: false,
+    })
+}
+
+/// Where the part of `output` that `final_newline` may still change starts:
+/// its last character other than a line break.
+fn held_end(output: &str, final_newline: FinalNewline) -> usize {
+    if final_newline == FinalNewline::Keep {
+        return output.len();
+    }
+    output
+        .trim_end_matches(['\r', '\n'])
+        .char_indices()
+        .next_back()
+        .map_or(0, |(start, _)| start)
+}
+
+/// Runs `work` over `jobs` on up to `threads` threads and returns the
+/// results in job order. A panic while processing a job becomes an error
+/// diagnostic for that file instead of aborting the run. Once `halt` holds
+/// for a result, no further jobs are started and their results are `None`.
+fn run_parallel(
+    jobs: &[Job],
+    threads: usize,
+    work: impl Fn(&Job) -> Result<Outcome, String> + Sync,
+    halt: impl Fn(&Result<Outcome, String>) -> bool + Sync,
+) -> Vec<Option<Result<Outcome, String>>> {
+    let next = AtomicUsize::new(0);
+    let halted = AtomicBool::new(false);
+    let run = |job: &Job| {
+        panic::catch_unwind(AssertUnwindSafe(|| work(job))).unwrap_or_else(|_| {
+            Ok(Outcome::failed(
+                Code::Internal,
+                "Internal error: the transpiler panicked on this file".to_string(),
+            ))
+        })
+    };
+
+    let results: Vec<(usize, Result<Outcome, String>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.clamp(1, jobs.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while !halted.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(index) else {
+                            break;
+                        };
+                        let result = run(job);
+                        if halt(&result) {
+                            halted.store(true, Ordering::Relaxed);
+                        }
+                        done.push((index, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("job panics are caught"))
+            .collect()
+    });
+    let mut ordered: Vec<Option<Result<Outcome, String>>> = jobs.iter().map(|_| None).collect();
+    for (index, result) in results {
+        ordered[index] = Some(result);
     }
+    ordered
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Human,
+    Json,
+}
 
-    let mut has_error = false;
-    for diagnostic in diagnostics {
-        match diagnostic.level {
-            Level::Warning => eprintln!("warning: {}", diagnostic.message),
-            Level::Error => {
-                has_error = true;
-                eprintln!("error: {}", diagnostic.message);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+struct Reporter {
+    format: Format,
+    quiet: bool,
+    color: bool,
+    entries: Vec<String>,
+    summaries: Vec<String>,
+    tags: Option<usize>,
+    errors: usize,
+    warnings: usize,
+}
+
+impl Reporter {
+    fn new(format: Format, quiet: bool, color: bool) -> Self {
+        Reporter {
+            format,
+            quiet,
+            color,
+            entries: Vec::new(),
+            summaries: Vec::new(),
+            tags: None,
+            errors: 0,
+            warnings: 0,
+        }
+    }
+
+    fn report(&mut self, job: &Job, diagnostics: Vec<Diagnostic>, tags: Option<usize>) {
+        let mut errors = 0;
+        let mut warnings = 0;
+        for diagnostic in diagnostics {
+            match diagnostic.level {
+                Level::Warning => warnings += 1,
+                Level::Error => errors += 1,
+            }
+            if self.quiet && diagnostic.level == Level::Warning {
+                continue;
+            }
+            match self.format {
+                Format::Human => print_diagnostic(job, &diagnostic, self.color),
+                Format::Json => self.entries.push(diagnostic_json(job, &diagnostic)),
             }
         }
+
+        self.errors += errors;
+        self.warnings += warnings;
+        if let Some(tags) = tags {
+            *self.tags.get_or_insert(0) += tags;
+            let summary = summary_line(tags, warnings, errors);
+            self.summaries.push(match job.input {
+                Some(ref path) => format!("{}: {summary}", path.display()),
+                None => summary,
+            });
+        }
     }
 
-    if has_error && config.check {
-        std::process::exit(1);
+    fn finish(&self) {
+        if self.format == Format::Json {
+            eprintln!("[{}]", self.entries.join(","));
+            return;
+        }
+
+        if self.quiet {
+            return;
+        }
+        let Some(tags) = self.tags else {
+            return;
+        };
+        if let [summary] = self.summaries.as_slice() {
+            eprintln!("{summary}");
+        } else {
+            for summary in &self.summaries {
+                eprintln!("{summary}");
+            }
+            eprintln!("total: {}", summary_line(tags, self.warnings, self.errors));
+        }
     }
 }
 
+fn summary_line(tags: usize, warnings: usize, errors: usize) -> String {
+    format!(
+        "{}, {}, {}",
+        plural(tags, "tag transformed", "tags transformed"),
+        plural(warnings, "warning", "warnings"),
+        plural(errors, "error", "errors")
+    )
+}
+
+fn plural(count: usize, singular: &str, plural: &str) -> String {
+    format!("{count} {}", if count == 1 { singular } else { plural })
+}
+
+fn print_diagnostic(job: &Job, diagnostic: &Diagnostic, color: bool) {
+    // Diagnostics about the whole file have no position.
+    let mut location = match diagnostic.span.line {
+        0 => Vec::new(),
+        line => vec![line.to_string(), diagnostic.span.column.to_string()],
+    };
+    if let Some(ref path) = job.input {
+        location.insert(0, path.display().to_string());
+    }
+    let mut location = location.join(":");
+    if !location.is_empty() {
+        location.push_str(": ");
+    }
+    let (label, ansi) = match diagnostic.level {
+        Level::Warning => ("warning", "33"),
+        Level::Error => ("error", "31"),
+    };
+    let prefix = format!("{label}[{}]", diagnostic.code);
+    if color {
+        eprintln!(
+            "\x1b[1;{ansi}m{prefix}\x1b[0m: {location}{}",
+            diagnostic.message
+        );
+    } else {
+        eprintln!("{prefix}: {location}{}", diagnostic.message);
+    }
+}
+
+fn diagnostic_json(job: &Job, diagnostic: &Diagnostic) -> String {
+    let level = match diagnostic.level {
+        Level::Warning => "warning",
+        Level::Error => "error",
+    };
+    let file = match job.input {
+        Some(ref path) => json_string(&path.display().to_string()),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"file\":{file},\"level\":\"{level}\",\"code\":\"{}\",\"message\":{},\"line\":{},\"column\":{}}}",
+        diagnostic.code,
+        json_string(&diagnostic.message),
+        diagnostic.span.line,
+        diagnostic.span.column
+    )
+}
+
+/// Lists every transformed tag as `file:line:column: before -> after`.
+fn trace(job: &Job, input: &str, segments: &[Segment]) -> String {
+    let prefix = match job.input {
+        Some(ref path) => format!("{}:", path.display()),
+        None => String::new(),
+    };
+    let mut trace = String::new();
+    let (mut line, mut column, mut offset) = (1, 1, 0);
+    for segment in segments.iter().filter(|segment| segment.tag) {
+        for character in input[offset..segment.source.start].chars() {
+            if character == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        offset = segment.source.start;
+        trace.push_str(&format!(
+            "{prefix}{line}:{column}: {} -> {}\n",
+            &input[segment.source.clone()],
+            segment.text
+        ));
+    }
+    trace
+}
+
+fn sourcemap_json(segments: &[Segment]) -> String {
+    let mappings: Vec<String> = segments
+        .iter()
+        .filter(|segment| segment.tag)
+        .map(|segment| {
+            format!(
+                "{{\"out_start\":{},\"out_len\":{},\"in_start\":{},\"in_len\":{}}}",
+                segment.output.start,
+                segment.output.len(),
+                segment.source.start,
+                segment.source.len()
+            )
+        })
+        .collect();
+    format!("[{}]\n", mappings.join(","))
+}
+
+/// The `--count-only` histogram of the constructs found across all inputs.
+fn count_report(counts: &[usize], format: Format) -> String {
+    let names = Stats::default().constructs().map(|(name, _)| name);
+    if format == Format::Json {
+        let fields: Vec<String> = names
+            .iter()
+            .zip(counts)
+            .map(|(name, count)| format!("{}:{count}", json_string(name)))
+            .collect();
+        return format!("{{{}}}\n", fields.join(","));
+    }
+    let width = counts
+        .iter()
+        .map(|count| count.to_string().len())
+        .max()
+        .unwrap_or(1);
+    names
+        .iter()
+        .zip(counts)
+        .map(|(name, count)| format!("{name:<10} {count:>width$}\n"))
+        .collect()
+}
+
+/// One `--manifest` object: the input and output paths, `null` for stdin
+/// and stdout, and how many warnings and errors the file reported.
+fn manifest_entry(job: &Job, diagnostics: &[Diagnostic]) -> String {
+    let path = |path: &Option<PathBuf>| match path {
+        Some(path) => json_string(&path.display().to_string()),
+        None => "null".to_string(),
+    };
+    let count = |level| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.level == level)
+            .count()
+    };
+    format!(
+        "{{\"input\":{},\"output\":{},\"warnings\":{},\"errors\":{}}}",
+        path(&job.input),
+        path(&job.output),
+        count(Level::Warning),
+        count(Level::Error)
+    )
+}
+
 #[derive(Debug)]
 struct Config {
-    input: Option<PathBuf>,
+    config_file: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
     output: Option<PathBuf>,
-    stdin: bool,
+    output_dir: Option<PathBuf>,
+    base: Option<PathBuf>,
+    create_dirs: bool,
     allow_parent: bool,
+    no_reformat: bool,
+    helpers: Option<PathBuf>,
+    config_helpers: HashMap<String, String>,
+    each_as_object: bool,
+    default_alias: Option<String>,
+    always_index: Option<String>,
+    range_helper: Option<String>,
+    indent: Option<usize>,
+    strip_comments: bool,
+    keep_bom: bool,
+    keep_empty_tags: bool,
+    comment_style: CommentStyle,
+    mustache_spacing: MustacheSpacing,
+    normalize_escaped_braces: bool,
+    standalone_whitespace: bool,
+    fail_unknown_helpers: bool,
+    case_insensitive_blocks: bool,
+    unescaped: Option<Level>,
+    passthrough_blocks: Vec<String>,
     check: bool,
+    strict: bool,
+    max_warnings: Option<usize>,
+    allow: HashSet<Code>,
+    check_only_syntax: bool,
+    count_only: bool,
+    check_idempotent: bool,
+    fail_fast: bool,
+    stream: bool,
+    write: bool,
+    ext: Option<String>,
+    diff: bool,
+    dry_run: bool,
+    sourcemap: Option<PathBuf>,
+    annotate: bool,
+    manifest: Option<PathBuf>,
+    final_newline: FinalNewline,
+    buffer_size: usize,
+    jobs: usize,
+    quiet: bool,
+    verbose: bool,
+    print_config: bool,
+    list_diagnostics: bool,
+    list_features: bool,
+    explain: Option<Code>,
+    format: Format,
+    color: ColorChoice,
 }
 
+#[derive(Debug)]
+struct Job {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    create_dirs: bool,
+    in_place: bool,
+}
+
+/// Defaults read from `.slinetranspiler.toml`.
+///
+/// Precedence, lowest first: built-in defaults, the config file, then
+/// command-line flags. Helpers from `--helpers` are merged over the
+/// `[helpers]` table of the config file.
+#[derive(Debug, Default)]
+struct FileConfig {
+    allow_parent: bool,
+    no_reformat: bool,
+    each_as_object: bool,
+    default_alias: Option<String>,
+    strict: bool,
+    quiet: bool,
+    helpers: HashMap<String, String>,
+}
+
+const CONFIG_FILE: &str = ".slinetranspiler.toml";
+
+/// Bytes of output buffered before a write, unless `--buffer-size` is given.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
 fn parse_args() -> Result<Config, String> {
-    let mut input = None;
+    let config_file = find_config_file();
+    let defaults = match config_file {
+        Some(ref path) => load_config(path)?,
+        None => FileConfig::default(),
+    };
+
+    let mut inputs = Vec::new();
     let mut output = None;
+    let mut output_dir = None;
+    let mut base = None;
+    let mut create_dirs = false;
     let mut stdin = false;
-    let mut allow_parent = false;
+    let mut allow_parent = defaults.allow_parent;
+    let mut no_reformat = defaults.no_reformat;
+    let mut helpers = None;
+    let mut each_as_object = defaults.each_as_object;
+    let mut default_alias = defaults.default_alias;
+    let mut always_index = false;
+    let mut index_alias = None;
+    let mut range_helper = None;
+    let mut indent = None;
+    let mut strip_comments = false;
+    let mut keep_bom = false;
+    let mut keep_empty_tags = false;
+    let mut comment_style = CommentStyle::default();
+    let mut mustache_spacing = MustacheSpacing::default();
+    let mut normalize_escaped_braces = false;
+    let mut standalone_whitespace = false;
+    let mut fail_unknown_helpers = false;
+    let mut case_insensitive_blocks = false;
+    let mut unescaped = None;
+    let mut passthrough_blocks = Vec::new();
     let mut check = false;
+    let mut strict = defaults.strict;
+    let mut max_warnings = None;
+    let mut allow = HashSet::new();
+    let mut check_only_syntax = false;
+    let mut count_only = false;
+    let mut check_idempotent = false;
+    let mut fail_fast = false;
+    let mut stream = false;
+    let mut recursive = false;
+    let mut write = false;
+    let mut ext = None;
+    let mut diff = false;
+    let mut dry_run = false;
+    let mut sourcemap = None;
+    let mut annotate = false;
+    let mut manifest = None;
+    let mut final_newline = FinalNewline::default();
+    let mut buffer_size = DEFAULT_BUFFER_SIZE;
+    let mut jobs = thread::available_parallelism().map_or(1, |count| count.get());
+    let mut quiet = defaults.quiet;
+    let mut verbose = false;
+    let mut print_config = false;
+    let mut list_diagnostics = false;
+    let mut list_features = false;
+    let mut explain = None;
+    let mut format = Format::Human;
+    let mut color = ColorChoice::Auto;
 
     let mut args = env::args().skip(1).peekable();
     while let Some(arg) = args.next() {
@@ -96,51 +860,397 @@ fn parse_args() -> Result<Config, String> {
                 std::process::exit(0);
             }
             "--" => {
-                for remaining in args {
-                    if input.is_some() {
-                        return Err("Only one input path is supported".to_string());
-                    }
-                    input = Some(PathBuf::from(remaining));
-                }
+                inputs.extend(args.map(PathBuf::from));
                 break;
             }
             "-o" | "--output" => {
                 let value = args.next().ok_or("Missing value for --output")?;
-                output = Some(PathBuf::from(value));
+                output = (value != "-").then(|| PathBuf::from(value));
+            }
+            "--output-dir" => {
+                let value = args.next().ok_or("Missing value for --output-dir")?;
+                output_dir = Some(PathBuf::from(value));
+            }
+            "--base" => {
+                let value = args.next().ok_or("Missing value for --base")?;
+                base = Some(PathBuf::from(value));
+            }
+            "--input-list" => {
+                let value = args.next().ok_or("Missing value for --input-list")?;
+                inputs.extend(load_input_list(Path::new(&value))?);
             }
-            "--stdin" => stdin = true,
+            "--create-dirs" => create_dirs = true,
+            "--stdin" | "-" => stdin = true,
             "--allow-parent" => allow_parent = true,
+            "--no-allow-parent" => allow_parent = false,
+            "--no-reformat" => no_reformat = true,
+            "--helpers" => {
+                let value = args.next().ok_or("Missing value for --helpers")?;
+                helpers = Some(PathBuf::from(value));
+            }
+            "--each-as-object" => each_as_object = true,
+            "--fail-unknown-helpers" => fail_unknown_helpers = true,
+            "--case-insensitive-blocks" => case_insensitive_blocks = true,
+            "--no-unescaped" => unescaped = Some(Level::Error),
+            "--warn-unescaped" => unescaped = Some(Level::Warning),
+            "--passthrough-block" => {
+                let value = args.next().ok_or("Missing value for --passthrough-block")?;
+                passthrough_blocks.push(value);
+            }
+            "--strip-comments" => strip_comments = true,
+            "--keep-bom" => keep_bom = true,
+            "--keep-empty-tags" => keep_empty_tags = true,
+            "--standalone-whitespace" => standalone_whitespace = true,
+            "--normalize-escaped-braces" => normalize_escaped_braces = true,
+            "--comment-style" => {
+                let value = args.next().ok_or("Missing value for --comment-style")?;
+                comment_style = match value.as_str() {
+                    "handlebars" => CommentStyle::Handlebars,
+                    "sline" => CommentStyle::Sline,
+                    "strip" => CommentStyle::Strip,
+                    _ => return Err(format!("Unknown comment style: {value}")),
+                };
+            }
+            "--mustache-spacing" => {
+                let value = args.next().ok_or("Missing value for --mustache-spacing")?;
+                mustache_spacing = match value.as_str() {
+                    "both" => MustacheSpacing::Both,
+                    "none" => MustacheSpacing::None,
+                    "helpers-only" => MustacheSpacing::HelpersOnly,
+                    _ => return Err(format!("Unknown mustache spacing: {value}")),
+                };
+            }
+            "--indent" => {
+                let value = args.next().ok_or("Missing value for --indent")?;
+                let width = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for --indent: {value}"))?;
+                indent = Some(width);
+            }
+            "--default-alias" => {
+                let value = args.next().ok_or("Missing value for --default-alias")?;
+                default_alias = Some(value);
+            }
+            "--always-index" => always_index = true,
+            "--range-helper" => {
+                let value = args.next().ok_or("Missing value for --range-helper")?;
+                range_helper = Some(value);
+            }
+            "--index-alias" => {
+                let value = args.next().ok_or("Missing value for --index-alias")?;
+                index_alias = Some(value);
+            }
             "--check" => check = true,
+            "--strict" => strict = true,
+            "--max-warnings" => {
+                let value = args.next().ok_or("Missing value for --max-warnings")?;
+                let count = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for --max-warnings: {value}"))?;
+                max_warnings = Some(count);
+            }
+            "--allow" => {
+                let value = args.next().ok_or("Missing value for --allow")?;
+                allow.insert(value.parse()?);
+            }
+            "--check-only-syntax" => check_only_syntax = true,
+            "--count-only" => count_only = true,
+            "--check-idempotent" => check_idempotent = true,
+            "--fail-fast" => fail_fast = true,
+            "--stream" => stream = true,
+            "-r" | "--recursive" => recursive = true,
+            "-w" | "--write" => write = true,
+            "--ext" => {
+                let value = args.next().ok_or("Missing value for --ext")?;
+                ext = Some(value.trim_start_matches('.').to_string());
+            }
+            "--diff" => diff = true,
+            "-n" | "--dry-run" => dry_run = true,
+            "--buffer-size" => {
+                let value = args.next().ok_or("Missing value for --buffer-size")?;
+                buffer_size = match value.parse() {
+                    Ok(size) if size > 0 => size,
+                    _ => return Err(format!("Invalid value for --buffer-size: {value}")),
+                };
+            }
+            "-j" | "--jobs" => {
+                let value = args.next().ok_or("Missing value for --jobs")?;
+                jobs = match value.parse() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("Invalid value for --jobs: {value}")),
+                };
+            }
+            "--sourcemap" => {
+                let value = args.next().ok_or("Missing value for --sourcemap")?;
+                sourcemap = Some(PathBuf::from(value));
+            }
+            "--annotate" => annotate = true,
+            "--manifest" => {
+                let value = args.next().ok_or("Missing value for --manifest")?;
+                manifest = Some(PathBuf::from(value));
+            }
+            "--final-newline" => {
+                let value = args.next().ok_or("Missing value for --final-newline")?;
+                final_newline = match value.as_str() {
+                    "keep" => FinalNewline::Keep,
+                    "ensure" => FinalNewline::Ensure,
+                    "strip" => FinalNewline::Strip,
+                    _ => return Err(format!("Unknown final newline policy: {value}")),
+                };
+            }
+            "-q" | "--quiet" => quiet = true,
+            "-v" | "--verbose" => verbose = true,
+            "--print-config" => print_config = true,
+            "--list-diagnostics" => list_diagnostics = true,
+            "--features" => list_features = true,
+            "--explain" => {
+                let value = args.next().ok_or("Missing value for --explain")?;
+                explain = Some(value.parse()?);
+            }
+            "--format" => {
+                let value = args.next().ok_or("Missing value for --format")?;
+                format = match value.as_str() {
+                    "human" => Format::Human,
+                    "json" => Format::Json,
+                    _ => return Err(format!("Unknown format: {value}")),
+                };
+            }
+            "--color" => {
+                let value = args.next().ok_or("Missing value for --color")?;
+                color = match value.as_str() {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => return Err(format!("Unknown color choice: {value}")),
+                };
+            }
             _ if arg.starts_with('-') => return Err(format!("Unknown option: {arg}")),
-            _ => {
-                if input.is_some() {
-                    return Err("Only one input path is supported".to_string());
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    // Quoted patterns (and every pattern on Windows) reach us unexpanded.
+    let mut expanded = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        match input.to_str() {
+            Some(pattern) if glob::is_pattern(pattern) && !input.exists() => {
+                let matches = glob::expand(pattern);
+                if matches.is_empty() {
+                    return Err(format!("No files match {pattern}"));
                 }
-                input = Some(PathBuf::from(arg));
+                expanded.extend(matches);
             }
+            _ => expanded.push(input),
         }
     }
+    let inputs = expanded;
 
-    if stdin && input.is_some() {
+    if let Some(ref alias) = default_alias
+        && !is_identifier(alias)
+    {
+        return Err(format!(
+            "--default-alias must be an identifier, got {alias}"
+        ));
+    }
+
+    if let Some(ref alias) = index_alias {
+        if !always_index {
+            return Err("--index-alias requires --always-index".to_string());
+        }
+        if !is_identifier(alias) {
+            return Err(format!("--index-alias must be an identifier, got {alias}"));
+        }
+    }
+    let always_index = always_index.then(|| index_alias.unwrap_or_else(|| "index".to_string()));
+
+    if stdin && !inputs.is_empty() {
         return Err("Use either --stdin or an input path, not both".to_string());
     }
 
-    if !stdin && input.is_none() {
+    if write && stdin {
+        return Err("--write needs input files and cannot be used with --stdin".to_string());
+    }
+
+    if write && output.is_some() {
+        return Err("Use either --write or --output, not both".to_string());
+    }
+
+    if output_dir.is_some() {
+        if output.is_some() || write {
+            return Err("Use only one of --output-dir, --output and --write".to_string());
+        }
+        if stdin {
+            return Err(
+                "--output-dir needs input files and cannot be used with --stdin".to_string(),
+            );
+        }
+    } else if base.is_some() {
+        return Err("--base only applies to --output-dir".to_string());
+    }
+
+    if let Some(ref ext) = ext {
+        if stdin || write {
+            return Err("--ext derives output names from input files and cannot be used with --stdin or --write".to_string());
+        }
+        if ext.is_empty() {
+            return Err("--ext needs a non-empty extension".to_string());
+        }
+        for input in &inputs {
+            let current = if input.is_dir() {
+                Some("hbs")
+            } else {
+                match input.extension().and_then(|current| current.to_str()) {
+                    Some(current) => Some(current),
+                    None => {
+                        return Err(format!(
+                            "--ext: {} has no extension to replace",
+                            input.display()
+                        ));
+                    }
+                }
+            };
+            if current == Some(ext.as_str()) {
+                return Err(format!("--ext {ext} would overwrite {}", input.display()));
+            }
+        }
+    }
+
+    if diff && (write || output.is_some() || output_dir.is_some()) {
+        return Err(
+            "--diff prints to stdout and cannot be combined with --write or --output".to_string(),
+        );
+    }
+
+    if dry_run && sourcemap.is_some() {
+        return Err(
+            "--dry-run never writes files and cannot be combined with --sourcemap".to_string(),
+        );
+    }
+
+    if verbose && format == Format::Json {
+        return Err("--verbose cannot be combined with --format json".to_string());
+    }
+
+    if sourcemap.is_some() && check_only_syntax {
+        return Err("--check-only-syntax produces no output to map".to_string());
+    }
+
+    if annotate && check_only_syntax {
+        return Err("--check-only-syntax produces no output to annotate".to_string());
+    }
+
+    if count_only
+        && (output.is_some()
+            || write
+            || diff
+            || dry_run
+            || sourcemap.is_some()
+            || annotate
+            || check_only_syntax
+            || stream)
+    {
+        return Err("--count-only produces no output and cannot be combined with --output, --write, --diff, --dry-run, --sourcemap, --annotate, --check-only-syntax or --stream".to_string());
+    }
+
+    if fail_fast && !(check || check_only_syntax || check_idempotent) {
+        return Err(
+            "--fail-fast only applies to --check, --check-only-syntax or --check-idempotent"
+                .to_string(),
+        );
+    }
+
+    if stream {
+        if !stdin {
+            return Err("--stream reads from stdin and needs --stdin".to_string());
+        }
+        if output.is_some()
+            || diff
+            || dry_run
+            || sourcemap.is_some()
+            || annotate
+            || check_only_syntax
+            || check_idempotent
+            || verbose
+        {
+            return Err("--stream writes to stdout as it converts and cannot be combined with --output, --diff, --dry-run, --sourcemap, --annotate, --check-only-syntax, --check-idempotent or --verbose".to_string());
+        }
+    }
+
+    if !stdin
+        && inputs.is_empty()
+        && !print_config
+        && !list_diagnostics
+        && !list_features
+        && explain.is_none()
+    {
         return Err("Provide an input path or use --stdin".to_string());
     }
 
-    if let Some(ref path) = input
-        && path.is_dir()
+    if !recursive && inputs.iter().any(|path| path.is_dir()) {
+        return Err("Directory inputs require --recursive".to_string());
+    }
+
+    if inputs.len() > 1
+        && let Some(ref path) = output
+        && path.is_file()
     {
-        return Err("Directory inputs are not supported yet".to_string());
+        return Err("--output must be a directory when transpiling multiple inputs".to_string());
     }
 
     Ok(Config {
-        input,
+        config_file,
+        inputs,
         output,
-        stdin,
+        output_dir,
+        base,
+        create_dirs,
         allow_parent,
+        no_reformat,
+        helpers,
+        config_helpers: defaults.helpers,
+        each_as_object,
+        default_alias,
+        always_index,
+        range_helper,
+        indent,
+        strip_comments,
+        keep_bom,
+        keep_empty_tags,
+        comment_style,
+        mustache_spacing,
+        normalize_escaped_braces,
+        standalone_whitespace,
+        fail_unknown_helpers,
+        case_insensitive_blocks,
+        unescaped,
+        passthrough_blocks,
         check,
+        strict,
+        max_warnings,
+        allow,
+        check_only_syntax,
+        count_only,
+        check_idempotent,
+        fail_fast,
+        stream,
+        write,
+        ext,
+        diff,
+        dry_run,
+        sourcemap,
+        annotate,
+        manifest,
+        final_newline,
+        buffer_size,
+        jobs,
+        quiet,
+        verbose,
+        print_config,
+        list_diagnostics,
+        list_features,
+        explain,
+        format,
+        color,
     })
 }
 
@@ -148,284 +1258,792 @@ fn print_help() {
     let help = r#"sline-transpiler - Handlebars to Sline converter
 
 USAGE:
-    sline-transpiler [OPTIONS] <input>
+    sline-transpiler [OPTIONS] <input>...
+    sline-transpiler [OPTIONS] "templates/**/*.hbs"
     sline-transpiler [OPTIONS] --stdin
 
 OPTIONS:
-    -o, --output <FILE>   Write output to file (default: stdout)
-    --stdin               Read input from stdin
-    --allow-parent        Strip ../ scope and emit warnings
+    -o, --output <FILE>   Write output to file (default: stdout, or -); with
+                          several inputs, --recursive or an existing
+                          directory, the directory to write results into
+    --output-dir <DIR>    Write every result into DIR, mirroring the input
+                          paths below their common directory
+    --base <DIR>          Directory that --output-dir paths are relative to
+    --create-dirs         Create missing parent directories of --output
+                          (always done when writing into a directory)
+    --input-list <FILE>   Also convert the paths listed in FILE, one per line
+                          (blank lines and # comments are skipped)
+    --stdin, -            Read input from stdin
+    --stream              With --stdin, convert and write the output as the
+                          input arrives instead of reading it all first
+    --allow-parent        Resolve ../ to outer loop aliases with warnings
+    --no-allow-parent     Reject ../ even if the config file allows it
+    --no-reformat         Keep the original spacing inside mustaches
+    --mustache-spacing <STYLE>
+                          Padding inside reformatted mustaches: both
+                          ({{ x }}, default), none ({{x}}) or helpers-only
+                          (only around expressions of several tokens)
+    --indent <N>          Re-indent lines inside blocks by N spaces per level
+    --strip-comments      Drop comments from the output
+    --keep-bom            Keep a leading UTF-8 byte order mark in the output
+    --keep-empty-tags     Keep empty {{}} tags in the output instead of
+                          removing them (they are still reported)
+    --comment-style <STYLE>
+                          What {{#comment}} blocks become: handlebars
+                          ({{!-- --}}, default), sline (kept) or strip
+    --standalone-whitespace
+                          Drop the indentation and line break around block
+                          tags and comments alone on their line, as
+                          Handlebars does when rendering
+    --normalize-escaped-braces
+                          Turn mustaches written with brace entities
+                          (&#123;&#123;x&#125;&#125;) into {{x}} in a raw block
+    --helpers <FILE>      Map Handlebars helpers to Sline (name=replacement lines)
+    --fail-unknown-helpers
+                          Report helpers that are neither built in nor
+                          mapped as errors instead of warnings
+    --case-insensitive-blocks
+                          Accept block keywords in any case, such as {{#EACH}}
+                          or {{/If}}, lowercasing them with a warning
+    --no-unescaped        Report every {{{x}}} and {{&x}} tag as an error, for
+                          reviewing raw HTML output (output is still written)
+    --warn-unescaped      Like --no-unescaped, but report them as warnings
+    --passthrough-block <NAME>
+                          Keep {{#NAME ...}} and {{/NAME}} as written while
+                          converting the tags inside (repeatable)
+    --each-as-object      Bind a key variable in every #each so @key converts
+    --default-alias <NAME>
+                          Loop alias for #each without block params
+                          (default: item)
+    --always-index        Bind an index in every #for, even when the #each
+                          has no index block param
+    --index-alias <NAME>  Name of the index bound by --always-index
+                          (default: index)
+    --range-helper <NAME> Helper whose (NAME start end) #each iterables
+                          become start..end ranges (default: range)
     --check               Exit with code 1 if errors are found
+    --strict              With --check, also fail on warnings
+    --max-warnings <N>    Exit with code 1 if there are more than N warnings
+    --allow <CODE>        Ignore diagnostics with this code, e.g. SL010
+                          (repeatable)
+    --check-only-syntax   Only validate block structure, produce no output
+    --count-only          Print how many #each, #if, #unless, #with, partials,
+                          unescaped tags and ../ references the inputs use,
+                          instead of the output
+    --check-idempotent    Fail if transpiling the output again changes it
+    --fail-fast           When checking, stop at the first file with an error
+                          instead of checking the rest
+    -r, --recursive       Transpile every .hbs file under a directory input
+    -w, --write           Overwrite the input files with the transpiled output
+    --ext <EXT>           Write each input next to itself (or into --output)
+                          with its extension replaced (default: sline)
+    --diff                Print a unified diff instead of the output; exits
+                          with code 1 when anything would change
+    -n, --dry-run         Report which files would change without writing
+                          anything; exits with code 1 if any would
+    -j, --jobs <N>        Files to transpile in parallel (default: CPU count)
+    --sourcemap <FILE>    Write a JSON map from output tags back to the input
+    --annotate            Add a {{!-- CODE: message --}} comment to the output
+                          after each tag a diagnostic was reported on
+    --manifest <FILE>     Write a JSON list of the files processed, with their
+                          output paths and warning and error counts
+    --final-newline <POLICY>
+                          Line breaks at the end of the output: keep
+                          (default), ensure exactly one, or strip them
+    --buffer-size <BYTES> Output bytes to buffer between writes
+                          (default: 65536)
+    --format <FORMAT>     Diagnostic format: human (default) or json
+    --color <WHEN>        Color diagnostics: auto (default), always or never;
+                          auto honors NO_COLOR
+    -q, --quiet           Do not print warnings or the conversion summary
+    -v, --verbose         Print each transformed tag before and after
+    --list-diagnostics    Print every diagnostic code with its level and
+                          what it reports, then exit
+    --explain <CODE>      Explain a diagnostic code and how to fix it, then exit
+    --features            Print the Handlebars constructs the transpiler
+                          recognizes and whether each is converted, then exit
+    --print-config        Print the settings in effect after merging the
+                          config file and flags, then exit
     -h, --help            Print help
     -V, --version         Print version
+
+EXIT CODES:
+    0    Success
+    1    Errors found while checking, output that would change with --diff
+         or --dry-run, or too many warnings
+    2    Invalid arguments or configuration
+    3    A file or stream could not be read or written
+
+CONFIG:
+    Defaults are read from the nearest .slinetranspiler.toml in the current
+    directory or its parents. Flags override config file values.
 "#;
     println!("{help}");
 }
 
-fn read_input(config: &Config) -> io::Result<String> {
-    if config.stdin {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)?;
-        Ok(buffer)
-    } else if let Some(ref path) = config.input {
-        fs::read_to_string(path)
-    } else {
-        Ok(String::new())
+fn level_name(code: Code) -> &'static str {
+    match code.level() {
+        Level::Warning => "warning",
+        Level::Error => "error",
     }
 }
 
-fn write_output(config: &Config, output: &str) -> io::Result<()> {
-    if let Some(ref path) = config.output {
-        fs::write(path, output)
-    } else {
-        let mut stdout = io::stdout();
-        stdout.write_all(output.as_bytes())?;
-        stdout.flush()
+/// The `--explain` text for `code`: its description and explanation.
+fn explain(code: Code, format: Format) -> String {
+    if format == Format::Json {
+        return format!(
+            "{{\"code\":{},\"level\":{},\"description\":{},\"explanation\":{}}}\n",
+            json_string(code.as_str()),
+            json_string(level_name(code)),
+            json_string(code.description()),
+            json_string(code.explanation())
+        );
     }
+    format!(
+        "{code} ({}): {}\n\n{}\n",
+        level_name(code),
+        code.description(),
+        code.explanation()
+    )
 }
 
-fn transpile(input: &str, options: &Options) -> (String, Vec<Diagnostic>) {
-    let mut diagnostics = Vec::new();
-    let mut output = String::with_capacity(input.len());
-    let mut index = 0;
-    let mut stack: Vec<BlockContext> = Vec::new();
+/// Every diagnostic code for `--list-diagnostics`, one per line or as JSON.
+fn diagnostic_catalog(format: Format) -> String {
+    if format == Format::Json {
+        let entries: Vec<String> = Code::ALL
+            .into_iter()
+            .map(|code| {
+                format!(
+                    "{{\"code\":{},\"level\":{},\"description\":{}}}",
+                    json_string(code.as_str()),
+                    json_string(level_name(code)),
+                    json_string(code.description())
+                )
+            })
+            .collect();
+        return format!("[{}]\n", entries.join(","));
+    }
 
-    while let Some(relative_start) = input[index..].find("{{") {
-        let start = index + relative_start;
-        output.push_str(&input[index..start]);
+    let mut catalog = String::new();
+    for code in Code::ALL {
+        catalog.push_str(&format!(
+            "{code}  {:<7}  {}\n",
+            level_name(code),
+            code.description()
+        ));
+    }
+    catalog
+}
 
-        let is_triple = input[start..].starts_with("{{{");
-        let open_len = if is_triple { 3 } else { 2 };
-        let close_seq = if is_triple { "}}}" } else { "}}" };
+/// The supported Handlebars constructs for `--features`, aligned in columns
+/// or as JSON.
+fn feature_catalog(format: Format) -> String {
+    let features = features();
+    if format == Format::Json {
+        let entries: Vec<String> = features
+            .iter()
+            .map(|feature| {
+                let example = feature.example.as_ref().map_or("null".to_string(), |example| {
+                    format!(
+                        "{{\"input\":{},\"output\":{}}}",
+                        json_string(&example.input),
+                        json_string(&example.output)
+                    )
+                });
+                format!(
+                    "{{\"construct\":{},\"support\":{},\"note\":{},\"code\":{},\"example\":{example}}}",
+                    json_string(&feature.construct),
+                    json_string(feature.support.as_str()),
+                    json_string(&feature.note),
+                    feature
+                        .code
+                        .map_or("null".to_string(), |code| json_string(code.as_str()))
+                )
+            })
+            .collect();
+        return format!("[{}]\n", entries.join(","));
+    }
 
-        let search_start = start + open_len;
-        let close_relative = match input[search_start..].find(close_seq) {
-            Some(value) => value,
-            None => {
-                output.push_str(&input[start..]);
-                return (output, diagnostics);
-            }
+    let width = features
+        .iter()
+        .map(|feature| feature.construct.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut catalog = String::new();
+    for feature in &features {
+        let code = feature
+            .code
+            .map_or(String::new(), |code| format!(" ({code})"));
+        catalog.push_str(&format!(
+            "{:<width$}  {:<11}  {}{code}\n",
+            feature.construct,
+            feature.support.as_str(),
+            feature.note
+        ));
+    }
+    catalog
+}
+
+/// A resolved setting, for `--print-config`.
+enum Setting {
+    Flag(bool),
+    Number(Option<usize>),
+    Text(Option<String>),
+    List(Vec<String>),
+}
+
+/// The settings in effect, named after the flags that set them.
+fn settings(config: &Config, options: &Options) -> Vec<(&'static str, Setting)> {
+    let path = |path: &Option<PathBuf>| {
+        Setting::Text(path.as_ref().map(|path| path.display().to_string()))
+    };
+    let mut helpers: Vec<String> = options
+        .helpers
+        .iter()
+        .map(|(name, replacement)| format!("{name}={replacement}"))
+        .collect();
+    helpers.sort();
+    let mut allow: Vec<String> = config.allow.iter().map(|code| code.to_string()).collect();
+    allow.sort();
+    let comment_style = match options.comment_style {
+        CommentStyle::Handlebars => "handlebars",
+        CommentStyle::Sline => "sline",
+        CommentStyle::Strip => "strip",
+    };
+    let mustache_spacing = match options.mustache_spacing {
+        MustacheSpacing::Both => "both",
+        MustacheSpacing::None => "none",
+        MustacheSpacing::HelpersOnly => "helpers-only",
+    };
+    let final_newline = match config.final_newline {
+        FinalNewline::Keep => "keep",
+        FinalNewline::Ensure => "ensure",
+        FinalNewline::Strip => "strip",
+    };
+    let format = match config.format {
+        Format::Human => "human",
+        Format::Json => "json",
+    };
+    let color = match config.color {
+        ColorChoice::Auto => "auto",
+        ColorChoice::Always => "always",
+        ColorChoice::Never => "never",
+    };
+
+    vec![
+        ("config-file", path(&config.config_file)),
+        (
+            "inputs",
+            Setting::List(
+                config
+                    .inputs
+                    .iter()
+                    .map(|input| input.display().to_string())
+                    .collect(),
+            ),
+        ),
+        ("output", path(&config.output)),
+        ("output-dir", path(&config.output_dir)),
+        ("base", path(&config.base)),
+        ("create-dirs", Setting::Flag(config.create_dirs)),
+        ("allow-parent", Setting::Flag(options.allow_parent)),
+        ("no-reformat", Setting::Flag(options.preserve_spacing)),
+        ("helpers", Setting::List(helpers)),
+        ("each-as-object", Setting::Flag(options.each_as_object)),
+        (
+            "fail-unknown-helpers",
+            Setting::Flag(options.fail_unknown_helpers),
+        ),
+        (
+            "case-insensitive-blocks",
+            Setting::Flag(options.case_insensitive_blocks),
+        ),
+        (
+            "unescaped",
+            Setting::Text(options.unescaped.map(|level| {
+                match level {
+                    Level::Warning => "warning",
+                    Level::Error => "error",
+                }
+                .to_string()
+            })),
+        ),
+        (
+            "passthrough-block",
+            Setting::List(options.passthrough_blocks.clone()),
+        ),
+        (
+            "default-alias",
+            Setting::Text(options.default_alias.clone()),
+        ),
+        ("always-index", Setting::Text(options.always_index.clone())),
+        ("range-helper", Setting::Text(options.range_helper.clone())),
+        ("indent", Setting::Number(options.indent)),
+        ("strip-comments", Setting::Flag(options.strip_comments)),
+        ("keep-bom", Setting::Flag(options.keep_bom)),
+        ("keep-empty-tags", Setting::Flag(options.keep_empty_tags)),
+        (
+            "comment-style",
+            Setting::Text(Some(comment_style.to_string())),
+        ),
+        (
+            "mustache-spacing",
+            Setting::Text(Some(mustache_spacing.to_string())),
+        ),
+        (
+            "normalize-escaped-braces",
+            Setting::Flag(options.normalize_escaped_braces),
+        ),
+        (
+            "standalone-whitespace",
+            Setting::Flag(options.standalone_whitespace),
+        ),
+        ("check", Setting::Flag(config.check)),
+        ("strict", Setting::Flag(config.strict)),
+        ("max-warnings", Setting::Number(config.max_warnings)),
+        ("allow", Setting::List(allow)),
+        ("check-only-syntax", Setting::Flag(config.check_only_syntax)),
+        ("count-only", Setting::Flag(config.count_only)),
+        ("check-idempotent", Setting::Flag(config.check_idempotent)),
+        ("fail-fast", Setting::Flag(config.fail_fast)),
+        ("stream", Setting::Flag(config.stream)),
+        ("write", Setting::Flag(config.write)),
+        ("ext", Setting::Text(config.ext.clone())),
+        ("diff", Setting::Flag(config.diff)),
+        ("dry-run", Setting::Flag(config.dry_run)),
+        ("sourcemap", path(&config.sourcemap)),
+        ("annotate", Setting::Flag(config.annotate)),
+        ("manifest", path(&config.manifest)),
+        (
+            "final-newline",
+            Setting::Text(Some(final_newline.to_string())),
+        ),
+        ("buffer-size", Setting::Number(Some(config.buffer_size))),
+        ("jobs", Setting::Number(Some(config.jobs))),
+        ("quiet", Setting::Flag(config.quiet)),
+        ("verbose", Setting::Flag(config.verbose)),
+        ("format", Setting::Text(Some(format.to_string()))),
+        ("color", Setting::Text(Some(color.to_string()))),
+    ]
+}
+
+/// Renders settings as `key = value` lines, or as one JSON object.
+fn print_settings(settings: &[(&str, Setting)], format: Format) -> String {
+    if format == Format::Json {
+        let fields: Vec<String> = settings
+            .iter()
+            .map(|(key, setting)| {
+                let value = match setting {
+                    Setting::Flag(flag) => flag.to_string(),
+                    Setting::Number(Some(number)) => number.to_string(),
+                    Setting::Text(Some(text)) => json_string(text),
+                    Setting::Number(None) | Setting::Text(None) => "null".to_string(),
+                    Setting::List(items) => {
+                        let items: Vec<String> =
+                            items.iter().map(|item| json_string(item)).collect();
+                        format!("[{}]", items.join(","))
+                    }
+                };
+                format!("{}:{value}", json_string(key))
+            })
+            .collect();
+        return format!("{{{}}}\n", fields.join(","));
+    }
+
+    let mut lines = String::new();
+    for (key, setting) in settings {
+        let value = match setting {
+            Setting::Flag(flag) => flag.to_string(),
+            Setting::Number(Some(number)) => number.to_string(),
+            Setting::Text(Some(text)) => text.clone(),
+            Setting::Number(None) | Setting::Text(None) => "(unset)".to_string(),
+            Setting::List(items) if items.is_empty() => "(none)".to_string(),
+            Setting::List(items) => items.join(", "),
         };
-        let end = search_start + close_relative;
-        let token_raw = &input[search_start..end];
-        let token_trim = token_raw.trim();
+        lines.push_str(&format!("{key} = {value}\n"));
+    }
+    lines
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+fn load_config(path: &Path) -> Result<FileConfig, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read config file {}: {err}", path.display()))?;
+    let mut config = FileConfig::default();
+    let mut in_helpers = false;
 
-        if token_trim.starts_with("!--") {
-            output.push_str(&input[start..end + close_seq.len()]);
-            index = end + close_seq.len();
+    for (number, line) in content.lines().enumerate() {
+        let error = |message: &str| format!("{}:{}: {message}", path.display(), number + 1);
+        let line = strip_toml_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            in_helpers = match section.trim() {
+                "helpers" => true,
+                other => return Err(error(&format!("unknown section [{other}]"))),
+            };
             continue;
         }
 
-        if token_trim.starts_with("#comment") {
-            if let Some(close_end) = find_block_close(input, end + close_seq.len(), "comment") {
-                let inner = &input[end + close_seq.len()..close_end.start];
-                output.push_str("{{!--");
-                output.push_str(inner);
-                output.push_str("--}}");
-                index = close_end.end;
-                continue;
-            } else {
-                diagnostics.push(Diagnostic {
-                    level: Level::Error,
-                    message: "Unclosed {{#comment}} block".to_string(),
-                });
-                output.push_str(&input[start..end + close_seq.len()]);
-                index = end + close_seq.len();
-                continue;
-            }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected key = value"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if in_helpers {
+            let name = toml_string(key).unwrap_or(key);
+            let mapped = toml_string(value).ok_or_else(|| error("expected a quoted string"))?;
+            config.helpers.insert(name.to_string(), mapped.to_string());
+            continue;
         }
 
-        let transformed = transform_tag(token_trim, &mut stack, options, &mut diagnostics);
-        if is_triple {
-            output.push_str("{{{ ");
-            output.push_str(&transformed);
-            output.push_str(" }}}");
-        } else {
-            output.push_str("{{ ");
-            output.push_str(&transformed);
-            output.push_str(" }}");
+        if key == "default_alias" {
+            let alias = toml_string(value).ok_or_else(|| error("expected a quoted string"))?;
+            config.default_alias = Some(alias.to_string());
+            continue;
+        }
+
+        let flag = match value {
+            "true" => true,
+            "false" => false,
+            _ => return Err(error(&format!("expected true or false for {key}"))),
+        };
+        match key {
+            "allow_parent" => config.allow_parent = flag,
+            "no_reformat" => config.no_reformat = flag,
+            "each_as_object" => config.each_as_object = flag,
+            "strict" => config.strict = flag,
+            "quiet" => config.quiet = flag,
+            _ => return Err(error(&format!("unknown key {key}"))),
         }
-        index = end + close_seq.len();
     }
+    Ok(config)
+}
 
-    output.push_str(&input[index..]);
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && name
+            .chars()
+            .all(|character| character.is_alphanumeric() || character == '_')
+}
 
-    if !stack.is_empty() {
-        for _context in stack {
-            diagnostics.push(Diagnostic {
-                level: Level::Error,
-                message: "Unclosed block: each".to_string(),
-            });
+fn strip_toml_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (offset, character) in line.char_indices() {
+        match (quote, character) {
+            (Some(open), _) if character == open => quote = None,
+            (None, '"' | '\'') => quote = Some(character),
+            (None, '#') => return &line[..offset],
+            _ => {}
         }
     }
-
-    (output, diagnostics)
+    line
 }
 
-struct BlockClose {
-    start: usize,
-    end: usize,
+fn toml_string(value: &str) -> Option<&str> {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .or_else(|| {
+            value
+                .strip_prefix('\'')
+                .and_then(|value| value.strip_suffix('\''))
+        })
 }
 
-fn find_block_close(source: &str, start_index: usize, name: &str) -> Option<BlockClose> {
-    let mut index = start_index;
-    let close_tag = format!("/{}", name);
-    while let Some(relative_start) = source[index..].find("{{") {
-        let open = index + relative_start;
-        let is_triple = source[open..].starts_with("{{{");
-        let open_len = if is_triple { 3 } else { 2 };
-        let close_seq = if is_triple { "}}}" } else { "}}" };
-        let search_start = open + open_len;
-        let close_relative = source[search_start..].find(close_seq)?;
-        let close = search_start + close_relative;
-        let token_raw = &source[search_start..close];
-        let token_trim = token_raw.trim();
-        if token_trim == close_tag {
-            return Some(BlockClose {
-                start: open,
-                end: close + close_seq.len(),
-            });
+fn load_helpers(path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read helpers file {}: {err}", path.display()))?;
+    let mut helpers = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-        index = close + close_seq.len();
+        let Some((name, mapped)) = line.split_once('=') else {
+            return Err(format!(
+                "{}:{}: expected handlebarsName=slineName",
+                path.display(),
+                number + 1
+            ));
+        };
+        helpers.insert(name.trim().to_string(), mapped.trim().to_string());
     }
-    None
+    Ok(helpers)
 }
 
-fn transform_tag(
-    tag: &str,
-    stack: &mut Vec<BlockContext>,
-    options: &Options,
-    diagnostics: &mut Vec<Diagnostic>,
-) -> String {
-    if let Some(rest) = tag.strip_prefix("#each") {
-        let (expr, alias) = parse_each(rest.trim());
-        stack.push(BlockContext::Each { alias: alias.clone() });
-        return format!("#for {} in {}", alias, expr);
+/// Reads template paths from a list file, one per line, skipping blank
+/// lines and `#` comments.
+fn load_input_list(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read input list {}: {err}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Plans one job per template, or fails with the exit code and message to
+/// stop with.
+fn plan_jobs(config: &Config) -> Result<Vec<Job>, (i32, String)> {
+    if let Some(ref dir) = config.output_dir {
+        return plan_output_dir(config, dir);
     }
 
-    if tag == "/each" {
-        match stack.pop() {
-            Some(BlockContext::Each { .. }) => {}
-            None => diagnostics.push(Diagnostic {
-                level: Level::Error,
-                message: "Unexpected closing tag /each".to_string(),
-            }),
+    plan_paths(config).map_err(|err| (EXIT_IO, format!("Failed to read input: {err}")))
+}
+
+/// Maps every template onto `dir`, keeping its path below `--base` or, by
+/// default, below the deepest directory containing all inputs.
+fn plan_output_dir(config: &Config, dir: &Path) -> Result<Vec<Job>, (i32, String)> {
+    let mut templates = Vec::new();
+    for input in &config.inputs {
+        if input.is_dir() {
+            collect_templates(input, &mut templates)
+                .map_err(|err| (EXIT_IO, format!("Failed to read input: {err}")))?;
+        } else {
+            templates.push(input.clone());
         }
-        return "/for".to_string();
     }
 
-    if let Some(rest) = tag.strip_prefix("#unless") {
-        let condition = rest.trim();
-        return format!("#if !({})", condition);
-    }
+    let base = match config.base {
+        Some(ref base) => normalize(base),
+        None => {
+            let roots: Vec<PathBuf> = config
+                .inputs
+                .iter()
+                .map(|input| {
+                    if input.is_dir() {
+                        normalize(input)
+                    } else {
+                        normalize(input.parent().unwrap_or(Path::new("")))
+                    }
+                })
+                .collect();
+            common_base(&roots)
+        }
+    };
 
-    if tag == "/unless" {
-        return "/if".to_string();
-    }
+    let ext = config.ext.as_deref().unwrap_or("sline");
+    templates
+        .into_iter()
+        .map(|path| {
+            let relative = normalize(&path)
+                .strip_prefix(&base)
+                .map(Path::to_path_buf)
+                .map_err(|_| {
+                    (
+                        EXIT_USAGE,
+                        format!("{} is not under --base {}", path.display(), base.display()),
+                    )
+                })?;
+            Ok(Job {
+                input: Some(path),
+                output: Some(dir.join(relative).with_extension(ext)),
+                create_dirs: true,
+                in_place: false,
+            })
+        })
+        .collect()
+}
+
+/// Drops `.` components so paths given as `./a` and `a` compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
 
-    if let Some(rest) = tag.strip_prefix("#if") {
-        let condition = rest.trim();
-        return format!("#if {}", condition);
+/// The longest leading run of components shared by all `paths`.
+fn common_base(paths: &[PathBuf]) -> PathBuf {
+    let Some((first, rest)) = paths.split_first() else {
+        return PathBuf::new();
+    };
+    let mut base: Vec<Component> = first.components().collect();
+    for path in rest {
+        let shared = base
+            .iter()
+            .zip(path.components())
+            .take_while(|(left, right)| **left == *right)
+            .count();
+        base.truncate(shared);
     }
+    base.into_iter().collect()
+}
 
-    if tag == "/if" {
-        return "/if".to_string();
+fn plan_paths(config: &Config) -> io::Result<Vec<Job>> {
+    if config.write {
+        let mut templates = Vec::new();
+        for input in &config.inputs {
+            if input.is_dir() {
+                collect_templates(input, &mut templates)?;
+            } else {
+                templates.push(input.clone());
+            }
+        }
+        return Ok(templates
+            .into_iter()
+            .map(|path| Job {
+                input: Some(path.clone()),
+                output: Some(path),
+                create_dirs: false,
+                in_place: true,
+            })
+            .collect());
     }
 
-    if tag == "else" {
-        return "else".to_string();
+    if config.inputs.is_empty() {
+        return Ok(vec![Job {
+            input: None,
+            output: config.output.clone(),
+            create_dirs: config.create_dirs,
+            in_place: false,
+        }]);
     }
 
-    if tag.starts_with("#with") || tag == "/with" {
-        diagnostics.push(Diagnostic {
-            level: Level::Warning,
-            message: "Handlebars #with blocks are not converted".to_string(),
-        });
-        return tag.to_string();
+    let ext = config.ext.as_deref().unwrap_or("sline");
+
+    if let [input] = config.inputs.as_slice()
+        && !input.is_dir()
+    {
+        let output = match config.output {
+            // `-o build/` names the directory to write the result into.
+            Some(ref dir) if dir.is_dir() || dir.as_os_str().to_string_lossy().ends_with('/') => {
+                Some(
+                    dir.join(input.file_name().unwrap_or(input.as_os_str()))
+                        .with_extension(ext),
+                )
+            }
+            Some(ref path) => Some(path.clone()),
+            None => config.ext.is_some().then(|| input.with_extension(ext)),
+        };
+        return Ok(vec![Job {
+            input: Some(input.clone()),
+            output,
+            create_dirs: config.create_dirs,
+            in_place: false,
+        }]);
     }
 
-    let current_alias = stack
-        .iter()
-        .rev()
-        .map(|context| match context {
-            BlockContext::Each { alias } => alias.as_str(),
-        })
-        .next();
+    let mut jobs = Vec::new();
+    for input in &config.inputs {
+        if !input.is_dir() {
+            let output = match config.output {
+                Some(ref dir) => Some(
+                    dir.join(input.file_name().unwrap_or(input.as_os_str()))
+                        .with_extension(ext),
+                ),
+                None => config.ext.is_some().then(|| input.with_extension(ext)),
+            };
+            jobs.push(Job {
+                input: Some(input.clone()),
+                output,
+                create_dirs: true,
+                in_place: false,
+            });
+            continue;
+        }
 
-    transform_expression(tag, current_alias, options, diagnostics)
+        let mut templates = Vec::new();
+        collect_templates(input, &mut templates)?;
+        jobs.extend(templates.into_iter().map(|path| {
+            let output = match config.output {
+                Some(ref dir) => dir.join(path.strip_prefix(input).unwrap_or(&path)),
+                None => path.clone(),
+            };
+            Job {
+                input: Some(path),
+                output: Some(output.with_extension(ext)),
+                create_dirs: config.output.is_some(),
+                in_place: false,
+            }
+        }));
+    }
+    Ok(jobs)
 }
 
-fn parse_each(rest: &str) -> (String, String) {
-    let marker = " as |";
-    if let Some(pos) = rest.find(marker) {
-        let expr = rest[..pos].trim();
-        let after = &rest[pos + marker.len()..];
-        if let Some(end) = after.find('|') {
-            let alias = after[..end].trim();
-            if !alias.is_empty() {
-                return (expr.to_string(), alias.to_string());
-            }
+fn collect_templates(dir: &Path, templates: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_templates(&path, templates)?;
+        } else if path.extension().is_some_and(|extension| extension == "hbs") {
+            templates.push(path);
         }
     }
-
-    (rest.trim().to_string(), "item".to_string())
+    Ok(())
 }
 
-fn transform_expression(
-    tag: &str,
-    alias: Option<&str>,
-    options: &Options,
-    diagnostics: &mut Vec<Diagnostic>,
-) -> String {
-    let mut content = tag.trim().to_string();
-
-    if content.starts_with("../") {
-        if options.allow_parent {
-            let mut stripped = content.as_str();
-            let mut count = 0;
-            while stripped.starts_with("../") {
-                stripped = &stripped[3..];
-                count += 1;
-            }
-            diagnostics.push(Diagnostic {
-                level: Level::Warning,
-                message: format!("Stripped {count} parent scope segments (../)"),
-            });
-            content = stripped.to_string();
-        } else {
-            diagnostics.push(Diagnostic {
-                level: Level::Error,
-                message: "Parent scope access (../) is not supported in Sline".to_string(),
-            });
-            return tag.to_string();
-        }
+fn read_input(job: &Job) -> io::Result<String> {
+    if let Some(ref path) = job.input {
+        fs::read_to_string(path)
+    } else {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        Ok(buffer)
     }
+}
 
-    if let Some(alias) = alias {
-        if content == "this" {
-            return alias.to_string();
+/// Writes `output` for `job` through a buffer of `buffer_size` bytes.
+fn write_output(job: &Job, output: &str, buffer_size: usize) -> io::Result<()> {
+    if let Some(ref path) = job.output {
+        if job.create_dirs
+            && let Some(parent) = path.parent()
+        {
+            fs::create_dir_all(parent).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not create {}: {err}", parent.display()),
+                )
+            })?;
         }
-        if let Some(rest) = content.strip_prefix("this.") {
-            return format!("{}.{}", alias, rest);
-        }
-        if let Some(rest) = content.strip_prefix("./") {
-            return format!("{}.{}", alias, rest);
+        if job.in_place {
+            write_atomically(path, output, buffer_size)
+        } else {
+            write_buffered(fs::File::create(path)?, output, buffer_size)
         }
     } else {
-        if content == "this" {
-            diagnostics.push(Diagnostic {
-                level: Level::Warning,
-                message: "Found {{this}} without an each context".to_string(),
-            });
-            return content;
-        }
-        if let Some(rest) = content.strip_prefix("this.") {
-            return rest.to_string();
-        }
-        if let Some(rest) = content.strip_prefix("./") {
-            return rest.to_string();
-        }
+        write_buffered(io::stdout().lock(), output, buffer_size)
     }
+}
 
-    content
+/// Writes `output` to `writer` at most `buffer_size` bytes at a time, so
+/// that a large output is handed to the writer in bounded pieces.
+fn write_buffered(writer: impl Write, output: &str, buffer_size: usize) -> io::Result<()> {
+    let mut writer = BufWriter::with_capacity(buffer_size, writer);
+    for piece in output.as_bytes().chunks(buffer_size) {
+        writer.write_all(piece)?;
+    }
+    writer.flush()
+}
+
+fn write_atomically(path: &Path, output: &str, buffer_size: usize) -> io::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".sline-tmp");
+    let temp_path = path.with_file_name(file_name);
+    fs::File::create(&temp_path)
+        .and_then(|file| write_buffered(file, output, buffer_size))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })?;
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
 }
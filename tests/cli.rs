@@ -0,0 +1,588 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Runs the binary with `args`, feeding it `stdin`.
+fn run(args: &[&str], stdin: &str) -> Output {
+    run_in(Path::new(env!("CARGO_MANIFEST_DIR")), args, stdin)
+}
+
+/// Like [`run`], from the working directory `directory`.
+fn run_in(directory: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sline-transpiler"))
+        .args(args)
+        .current_dir(directory)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the binary runs");
+    // Written from another thread so that output filling its pipe cannot
+    // block the child while the input is still being fed.
+    let mut pipe = child.stdin.take().expect("stdin is piped");
+    let stdin = stdin.to_string();
+    let writer = std::thread::spawn(move || pipe.write_all(stdin.as_bytes()));
+    let output = child.wait_with_output().expect("the binary finishes");
+    // A run with file inputs may exit without reading stdin at all.
+    if let Err(err) = writer.join().expect("the writer finishes") {
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe, "{err}");
+    }
+    output
+}
+
+/// A fresh directory for `test`, holding `files` as `(name, contents)`.
+fn directory(test: &str, files: &[(String, String)]) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("sline-transpiler-{test}-{}", std::process::id()));
+    fs::create_dir_all(&directory).expect("the directory is created");
+    for (name, contents) in files {
+        fs::write(directory.join(name), contents).expect("the file is written");
+    }
+    directory
+}
+
+fn exit_code(output: &Output) -> i32 {
+    output.status.code().expect("the binary exits normally")
+}
+
+#[test]
+fn only_check_with_strict_fails_on_warnings() {
+    for (flags, expected) in [
+        (&[][..], 0),
+        (&["--strict"][..], 0),
+        (&["--check"][..], 0),
+        (&["--check", "--strict"][..], 1),
+    ] {
+        let mut args = vec!["--stdin"];
+        args.extend(flags);
+        let output = run(&args, "{{foo x}}");
+        assert_eq!(exit_code(&output), expected, "{flags:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("1 warning, 0 errors"), "{stderr}");
+    }
+}
+
+#[test]
+fn results_do_not_depend_on_the_number_of_jobs() {
+    let files: Vec<(String, String)> = (0..40)
+        .map(|number| {
+            (
+                format!("t{number:02}.hbs"),
+                format!("{{{{#each x}}}}{{{{foo{number} ../a}}}}{{{{@index}}}}{{{{/each}}}}\n{{{{bar}}}} {{{{#if}}}}{{{{/if}}}}\n"),
+            )
+        })
+        .collect();
+    let directory = directory("jobs", &files);
+    let paths: Vec<String> = files
+        .iter()
+        .map(|(name, _)| directory.join(name).display().to_string())
+        .collect();
+
+    for format in ["human", "json"] {
+        let outputs: Vec<Output> = ["1", "8"]
+            .iter()
+            .map(|jobs| {
+                let mut args: Vec<&str> = paths.iter().map(String::as_str).collect();
+                args.extend(["--format", format, "--jobs", jobs]);
+                run(&args, "")
+            })
+            .collect();
+        assert_eq!(outputs[0].stdout, outputs[1].stdout);
+        assert_eq!(outputs[0].stderr, outputs[1].stderr);
+        assert!(!outputs[0].stdout.is_empty());
+        assert!(!outputs[0].stderr.is_empty());
+    }
+}
+
+#[test]
+fn buffer_size_does_not_change_the_output() {
+    let input = "<li>{{#if this.a}}{{this.b}} é 😀{{else}}{{c}}{{/if}}</li>\n".repeat(5_000);
+    let directory = directory("buffer", &[("big.hbs".to_string(), input.clone())]);
+    let path = directory.join("big.hbs").display().to_string();
+    let expected = run(&["--stdin", "-q"], &input).stdout;
+    assert!(expected.len() > input.len() / 2);
+
+    for size in ["1", "7", "65536"] {
+        assert_eq!(
+            run(&["--stdin", "-q", "--buffer-size", size], &input).stdout,
+            expected
+        );
+        assert_eq!(
+            run(
+                &["--stdin", "--stream", "-q", "--buffer-size", size],
+                &input
+            )
+            .stdout,
+            expected
+        );
+        let written = directory.join(format!("big-{size}.sline"));
+        let written_path = written.display().to_string();
+        run(
+            &[&path, "-o", &written_path, "-q", "--buffer-size", size],
+            "",
+        );
+        assert_eq!(fs::read(&written).expect("the output is written"), expected);
+    }
+}
+
+#[test]
+fn write_replaces_the_input_file() {
+    let directory = directory(
+        "write",
+        &[(
+            "page.hbs".to_string(),
+            "{{#if a}}{{b}}{{/if}}\n".to_string(),
+        )],
+    );
+    let path = directory.join("page.hbs");
+    let path_arg = path.display().to_string();
+    let output = run(&[&path_arg, "--write"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&path).expect("the file is read"),
+        "{{ #if a }}{{ b }}{{ /if }}\n"
+    );
+    let entries = fs::read_dir(&directory)
+        .expect("the directory is read")
+        .count();
+    assert_eq!(entries, 1, "no temporary file is left behind");
+
+    let output = run(&["--stdin", "--write"], "{{x}}");
+    assert_eq!(exit_code(&output), 2);
+    let other = directory.join("other.sline").display().to_string();
+    let output = run(&[&path_arg, "--write", "-o", &other], "");
+    assert_eq!(exit_code(&output), 2);
+}
+
+#[test]
+fn quiet_hides_warnings_but_not_errors() {
+    let input = "{{foo x}}{{#if}}{{/if}}";
+    let loud = run(&["--stdin"], input);
+    let quiet = run(&["--stdin", "--quiet"], input);
+    let stderr = String::from_utf8_lossy(&quiet.stderr);
+    assert!(stderr.contains("error[SL035]"), "{stderr}");
+    assert!(!stderr.contains("warning"), "{stderr}");
+    assert_eq!(quiet.stdout, loud.stdout);
+    assert_eq!(exit_code(&quiet), exit_code(&loud));
+
+    let output = run(&["--stdin", "--quiet", "--check", "--strict"], "{{foo x}}");
+    assert_eq!(exit_code(&output), 1);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn comment_style_selects_the_comment_output() {
+    for (style, expected) in [
+        ("handlebars", "a{{!-- x --}}b"),
+        ("sline", "a{{#comment}} x {{/comment}}b"),
+        ("strip", "ab"),
+    ] {
+        let output = run(
+            &["--stdin", "-q", "--comment-style", style],
+            "a{{#comment}} x {{/comment}}b",
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+    let output = run(&["--stdin", "--comment-style", "html"], "");
+    assert_eq!(exit_code(&output), 2);
+}
+
+#[test]
+fn a_missing_input_does_not_stop_the_others() {
+    let directory = directory(
+        "missing",
+        &[
+            ("a.hbs".to_string(), "{{x}}\n".to_string()),
+            ("c.hbs".to_string(), "{{y}}\n".to_string()),
+        ],
+    );
+    let paths: Vec<String> = ["a.hbs", "missing.hbs", "c.hbs"]
+        .iter()
+        .map(|name| directory.join(name).display().to_string())
+        .collect();
+    let args: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let output = run(&args, "");
+    assert_eq!(exit_code(&output), 3);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ x }}\n{{ y }}\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("error[SL097]: {}: Failed to read input", paths[1])),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("total: 2 tags transformed, 0 warnings, 1 error"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn input_lists_skip_comments_and_blank_lines() {
+    let directory = directory(
+        "input-list",
+        &[
+            ("a.hbs".to_string(), "{{a}}".to_string()),
+            ("b.hbs".to_string(), "{{b}}".to_string()),
+        ],
+    );
+    let list = format!(
+        "# templates\n\n{}\n  \n# skipped\n{}\n",
+        directory.join("a.hbs").display(),
+        directory.join("b.hbs").display()
+    );
+    let list_path = directory.join("list.txt");
+    fs::write(&list_path, list).expect("the list is written");
+    let list_arg = list_path.display().to_string();
+    let output = run(&["--input-list", &list_arg, "-q"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "{{ a }}{{ b }}");
+
+    let missing = directory.join("missing.txt").display().to_string();
+    let output = run(&["--input-list", &missing], "");
+    assert_eq!(exit_code(&output), 2);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to read input list"), "{stderr}");
+}
+
+#[test]
+fn always_index_takes_its_name_from_index_alias() {
+    let input = "{{#each items}}{{@index}}{{/each}}";
+    let output = run(&["--stdin", "-q", "--always-index"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for item, index in items }}{{ index }}{{ /for }}"
+    );
+    let output = run(
+        &["--stdin", "-q", "--always-index", "--index-alias", "n"],
+        input,
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for item, n in items }}{{ n }}{{ /for }}"
+    );
+}
+
+#[test]
+fn range_helper_names_the_range_subexpression() {
+    let output = run(
+        &["--stdin", "-q", "--range-helper", "span"],
+        "{{#each (span 1 3)}}{{/each}}",
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for item in 1..3 }}{{ /for }}"
+    );
+}
+
+#[test]
+fn mustache_spacing_accepts_each_mode() {
+    for (mode, expected) in [
+        ("both", "{{ x }} {{ t a }}"),
+        ("none", "{{x}} {{t a}}"),
+        ("helpers-only", "{{x}} {{ t a }}"),
+    ] {
+        let output = run(
+            &["--stdin", "-q", "--mustache-spacing", mode],
+            "{{x}} {{t a}}",
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{mode}");
+    }
+    let output = run(&["--stdin", "--mustache-spacing", "tabs"], "");
+    assert_eq!(exit_code(&output), 2);
+}
+
+#[test]
+fn no_unescaped_reports_every_raw_output() {
+    let input = "{{{a}}} {{b}} {{{d}}}";
+    let output = run(&["--stdin", "--no-unescaped"], input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("error[SL041]").count(), 2, "{stderr}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{{ a }}} {{ b }} {{{ d }}}"
+    );
+
+    let output = run(&["--stdin", "--warn-unescaped"], input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("warning[SL041]").count(), 2, "{stderr}");
+}
+
+#[test]
+fn keep_empty_tags_passes_empty_tags_through() {
+    let output = run(&["--stdin", "-q"], "a{{}}b");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ab");
+    let output = run(&["--stdin", "-q", "--keep-empty-tags"], "a{{}}b");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a{{  }}b");
+}
+
+#[test]
+fn crlf_fixture_converts_byte_for_byte() {
+    let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("example");
+    let input = example.join("test-crlf.html").display().to_string();
+    let expected = fs::read(example.join("test-crlf-converted.html")).expect("the fixture is read");
+    let output = run(&[&input, "-q"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert_eq!(output.stdout, expected);
+
+    let stdout = String::from_utf8(output.stdout).expect("the output is UTF-8");
+    assert_eq!(stdout.matches('\n').count(), stdout.matches("\r\n").count());
+    assert!(stdout.starts_with("<ul>\r\n  {{ #for product, index in products }}\r\n"));
+}
+
+#[test]
+fn no_allow_parent_overrides_the_config_file() {
+    let directory = directory(
+        "config",
+        &[(
+            ".slinetranspiler.toml".to_string(),
+            "allow_parent = true\n".to_string(),
+        )],
+    );
+    let input = "{{#each items}}{{../title}}{{/each}}";
+
+    let output = run_in(&directory, &["--stdin"], input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning[SL002]"), "{stderr}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for item in items }}{{ title }}{{ /for }}"
+    );
+
+    let output = run_in(&directory, &["--stdin", "--no-allow-parent"], input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error[SL001]: 1:16: Parent scope access (../) is not supported in Sline"),
+        "{stderr}"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for item in items }}{{ ../title }}{{ /for }}"
+    );
+}
+
+#[test]
+fn max_warnings_fails_only_past_the_limit() {
+    let input = "{{foo x}}{{bar y}}";
+    for (limit, expected) in [("0", 1), ("1", 1), ("2", 0), ("3", 0)] {
+        let output = run(&["--stdin", "-q", "--max-warnings", limit], input);
+        assert_eq!(exit_code(&output), expected, "--max-warnings {limit}");
+    }
+    let output = run(&["--stdin", "-q", "--max-warnings", "1"], input);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Too many warnings: 2 (limit 1)\n"
+    );
+
+    let directory = directory(
+        "max-warnings",
+        &[
+            ("a.hbs".to_string(), "{{foo x}}".to_string()),
+            ("b.hbs".to_string(), "{{bar y}}".to_string()),
+        ],
+    );
+    let directory = directory.display().to_string();
+    for (limit, expected) in [("1", 1), ("2", 0)] {
+        let output = run(&["-r", &directory, "-q", "--max-warnings", limit], "");
+        assert_eq!(exit_code(&output), expected, "-r --max-warnings {limit}");
+    }
+}
+
+#[test]
+fn default_alias_names_the_loop_variable() {
+    let output = run(
+        &["--stdin", "-q", "--default-alias", "row"],
+        "{{#each items}}{{this}} {{name}}{{/each}}",
+    );
+    assert_eq!(exit_code(&output), 0);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{{ #for row in items }}{{ row }} {{ name }}{{ /for }}"
+    );
+
+    let output = run(&["--stdin", "--default-alias", "1x"], "");
+    assert_eq!(exit_code(&output), 2);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "--default-alias must be an identifier, got 1x\n"
+    );
+}
+
+#[test]
+fn create_dirs_writes_into_a_nested_missing_directory() {
+    let directory = directory(
+        "create-dirs",
+        &[("a.hbs".to_string(), "{{x}}\n".to_string())],
+    );
+    let _ = fs::remove_dir_all(directory.join("out"));
+
+    let output = run_in(&directory, &["a.hbs", "-o", "out/deep/a.sline"], "");
+    assert_eq!(exit_code(&output), 3);
+    assert!(!directory.join("out").exists());
+
+    let output = run_in(
+        &directory,
+        &["a.hbs", "-o", "out/deep/a.sline", "--create-dirs"],
+        "",
+    );
+    assert_eq!(exit_code(&output), 0);
+    assert_eq!(
+        fs::read_to_string(directory.join("out/deep/a.sline")).expect("the output is written"),
+        "{{ x }}\n"
+    );
+
+    let output = run_in(&directory, &["a.hbs", "--output-dir", "out/gen/x"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert!(directory.join("out/gen/x/a.sline").exists());
+
+    fs::write(directory.join("out/file"), "").expect("the file is written");
+    let output = run_in(
+        &directory,
+        &["a.hbs", "-o", "out/file/x/a.sline", "--create-dirs"],
+        "",
+    );
+    assert_eq!(exit_code(&output), 3);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("could not create out/file/x"), "{stderr}");
+}
+
+#[test]
+fn each_kind_of_failure_has_its_own_exit_code() {
+    let directory = directory(
+        "exit-codes",
+        &[
+            ("good.hbs".to_string(), "{{x}}\n".to_string()),
+            ("bad.hbs".to_string(), "{{#if a}}\n".to_string()),
+            ("file".to_string(), String::new()),
+        ],
+    );
+    let cases: [(&[&str], i32); 6] = [
+        (&["good.hbs"], 0),
+        // Template errors only fail the run under --check.
+        (&["bad.hbs"], 0),
+        (&["bad.hbs", "--check"], 1),
+        (&["--bogus"], 2),
+        (&["missing.hbs"], 3),
+        (&["good.hbs", "-o", "file/good.sline"], 3),
+    ];
+    for (args, code) in cases {
+        let output = run_in(&directory, args, "");
+        assert_eq!(exit_code(&output), code, "{args:?}");
+    }
+}
+
+#[test]
+fn explain_prints_a_known_code_and_rejects_an_unknown_one() {
+    let output = run(&["--explain", "SL003"], "");
+    assert_eq!(exit_code(&output), 0);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("SL003 (warning): `{{this}}` or a loop data variable"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("only mean something inside an `#each`"),
+        "{stdout}"
+    );
+
+    let output = run(&["--explain", "SL999"], "");
+    assert_eq!(exit_code(&output), 2);
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Unknown diagnostic code: SL999\n"
+    );
+}
+
+#[test]
+fn output_naming_a_directory_gets_the_input_file_name() {
+    let directory = directory(
+        "output-directory",
+        &[("a.hbs".to_string(), "{{x}}\n".to_string())],
+    );
+    let _ = fs::remove_dir_all(directory.join("build"));
+    fs::create_dir(directory.join("build")).expect("the directory is created");
+
+    let output = run_in(&directory, &["a.hbs", "-o", "build"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(directory.join("build/a.sline")).expect("the output is written"),
+        "{{ x }}\n"
+    );
+
+    let output = run_in(&directory, &["a.hbs", "-o", "build/", "--ext", "html"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert!(directory.join("build/a.html").exists());
+
+    // Any other path is written as named.
+    let output = run_in(&directory, &["a.hbs", "-o", "build/page"], "");
+    assert_eq!(exit_code(&output), 0);
+    assert!(directory.join("build/page").is_file());
+}
+
+#[test]
+fn manifest_lists_every_file_processed() {
+    let directory = directory(
+        "manifest",
+        &[
+            ("a.hbs".to_string(), "{{x}}\n".to_string()),
+            ("b.hbs".to_string(), "{{#if a}}{{this}}\n".to_string()),
+        ],
+    );
+    let output = run_in(
+        &directory,
+        &[
+            "a.hbs",
+            "b.hbs",
+            "--ext",
+            "out",
+            "--manifest",
+            "manifest.json",
+            "--check",
+        ],
+        "",
+    );
+    // The manifest is written even though b.hbs fails the check.
+    assert_eq!(exit_code(&output), 1);
+    assert_eq!(
+        fs::read_to_string(directory.join("manifest.json")).expect("the manifest is written"),
+        concat!(
+            r#"[{"input":"a.hbs","output":"a.out","warnings":0,"errors":0},"#,
+            r#"{"input":"b.hbs","output":"b.out","warnings":1,"errors":1}]"#,
+            "\n"
+        )
+    );
+}
+
+#[test]
+fn check_only_syntax_writes_no_output() {
+    let directory = directory(
+        "check-only-syntax",
+        &[
+            ("good.hbs".to_string(), "{{x}}\n".to_string()),
+            ("open.hbs".to_string(), "{{#each a}}{{x}}\n".to_string()),
+        ],
+    );
+    for (input, code) in [("open.hbs", 1), ("good.hbs", 0)] {
+        let _ = fs::remove_file(directory.join("out.sline"));
+        let output = run_in(
+            &directory,
+            &[input, "--check-only-syntax", "-o", "out.sline"],
+            "",
+        );
+        assert_eq!(exit_code(&output), code, "{input}");
+        assert!(output.stdout.is_empty());
+        assert!(!directory.join("out.sline").exists(), "{input}");
+    }
+
+    let output = run_in(&directory, &["open.hbs", "--check-only-syntax"], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error[SL031]: open.hbs:1:1: Unclosed block: each"),
+        "{stderr}"
+    );
+}
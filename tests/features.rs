@@ -0,0 +1,22 @@
+use std::path::Path;
+use std::process::Command;
+
+/// The library builds without the `fs` feature, and so without file IO.
+#[test]
+fn library_builds_without_default_features() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // A target directory of its own, so the build does not wait on the lock
+    // held by the `cargo test` running this.
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--lib", "--no-default-features"])
+        .arg("--target-dir")
+        .arg(manifest_dir.join("target/no-default-features"))
+        .current_dir(manifest_dir)
+        .output()
+        .expect("cargo runs");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}